@@ -0,0 +1,271 @@
+//! トッピングの名前と価格を設定ファイルから読み込むモジュール
+//!
+//! 設定ファイルはTOMLかJSONで、トッピングのキーごとに表示名と価格を書く
+//! 追加できる数の上限、一緒に追加できないトッピング、追加できるドリンクも書ける
+//!
+//! ```toml
+//! [toppings.milk]
+//! name = "milk topping"
+//! price = "USD 3.30"
+//! max = 3
+//! excludes = ["soy"]
+//! drinks = ["coffee drink", "black tea"]
+//! ```
+use crate::money::Money;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// 1種類のトッピングの設定
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ToppingSpec {
+    /// レシートに表示する名前
+    pub name: String,
+    /// 価格  
+    /// 設定ファイルでは`"USD 3.30"`のように通貨コードと金額を書く
+    pub price: Money,
+    /// 1杯に追加できる数の上限
+    /// 書かなければ上限は無い
+    pub max: Option<u32>,
+    /// 一緒に追加できないトッピングのキー
+    /// 片方のトッピングに書けば、もう片方に書かなくても両方向に適用する
+    #[serde(default)]
+    pub excludes: Vec<String>,
+    /// 追加できるドリンクの名前
+    /// 書かなければどのドリンクにも追加できる
+    #[serde(default)]
+    pub drinks: Vec<String>,
+}
+
+/// 注文できるトッピングの一覧
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ToppingCatalogue {
+    /// キーはadd_toppingで指定するトッピングの識別子
+    toppings: BTreeMap<String, ToppingSpec>,
+}
+
+/// トッピングの一覧を読み込めなかった理由
+#[derive(Debug)]
+pub enum CatalogueError {
+    /// ファイルを読み込めなかった
+    Io(std::io::Error),
+    /// TOMLとして解釈できなかった
+    Toml(toml::de::Error),
+    /// JSONとして解釈できなかった
+    Json(serde_json::Error),
+    /// 拡張子がtomlでもjsonでもない
+    UnsupportedFormat(String),
+}
+
+impl fmt::Display for CatalogueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CatalogueError::Io(e) => write!(f, "failed to read the topping catalogue: {}", e),
+            CatalogueError::Toml(e) => write!(f, "invalid TOML topping catalogue: {}", e),
+            CatalogueError::Json(e) => write!(f, "invalid JSON topping catalogue: {}", e),
+            CatalogueError::UnsupportedFormat(path) => {
+                write!(f, "'{}' is neither a .toml nor a .json file", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CatalogueError {}
+
+/// 一覧に無いトッピングが指定された
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTopping(pub String);
+
+impl fmt::Display for UnknownTopping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown topping '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownTopping {}
+
+/// トッピングの組み合わせが守っていないルール
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// 一覧に無いトッピング
+    Unknown(String),
+    /// 上限を超える数のトッピングを追加した
+    TooMany { topping: String, max: u32 },
+    /// 一緒に追加できないトッピングを両方追加した
+    Exclusive { topping: String, other: String },
+    /// トッピングを追加できないドリンクに追加した
+    NotForDrink { topping: String, drink: String },
+    /// 追加していないトッピングを削除や差し替えしようとした
+    NotAdded(String),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Unknown(topping) => write!(f, "unknown topping '{}'", topping),
+            Violation::TooMany { topping, max } => {
+                write!(f, "'{}' can be added at most {} times", topping, max)
+            }
+            Violation::Exclusive { topping, other } => {
+                write!(f, "'{}' cannot be combined with '{}'", topping, other)
+            }
+            Violation::NotForDrink { topping, drink } => {
+                write!(f, "'{}' cannot be added to '{}'", topping, drink)
+            }
+            Violation::NotAdded(topping) => write!(f, "'{}' has not been added", topping),
+        }
+    }
+}
+
+/// トッピングの組み合わせがルールを守っていない
+/// 守っていない全てのルールを持つ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError(pub Vec<Violation>);
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let violations: Vec<String> = self.0.iter().map(|v| v.to_string()).collect();
+        write!(f, "invalid toppings: {}", violations.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<UnknownTopping> for ValidationError {
+    fn from(e: UnknownTopping) -> ValidationError {
+        ValidationError(vec![Violation::Unknown(e.0)])
+    }
+}
+
+impl ToppingCatalogue {
+    /// TOML形式の設定からトッピングの一覧を作る
+    pub fn from_toml(text: &str) -> Result<ToppingCatalogue, CatalogueError> {
+        toml::from_str(text).map_err(CatalogueError::Toml)
+    }
+
+    /// JSON形式の設定からトッピングの一覧を作る
+    pub fn from_json(text: &str) -> Result<ToppingCatalogue, CatalogueError> {
+        serde_json::from_str(text).map_err(CatalogueError::Json)
+    }
+
+    /// 設定ファイルを読み込む
+    /// 形式は拡張子で判断する
+    pub fn load(path: &Path) -> Result<ToppingCatalogue, CatalogueError> {
+        let text = fs::read_to_string(path).map_err(CatalogueError::Io)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => ToppingCatalogue::from_toml(&text),
+            Some("json") => ToppingCatalogue::from_json(&text),
+            _ => Err(CatalogueError::UnsupportedFormat(
+                path.display().to_string(),
+            )),
+        }
+    }
+
+    /// keyで指定されたトッピングの設定を返す
+    pub fn get(&self, key: &str) -> Result<&ToppingSpec, UnknownTopping> {
+        self.toppings
+            .get(key)
+            .ok_or_else(|| UnknownTopping(key.to_string()))
+    }
+
+    /// drinkにtoppingsのキーのトッピングを追加できるか確かめる
+    /// 守っていないルールがあれば、その全てをValidationErrorで返す
+    pub fn validate(&self, drink: &str, toppings: &[&str]) -> Result<(), ValidationError> {
+        let mut counts: BTreeMap<&str, u32> = BTreeMap::new();
+        for key in toppings {
+            *counts.entry(key).or_default() += 1;
+        }
+        let mut violations = Vec::new();
+        // 両方のトッピングに書かれていても1度だけ報告する
+        let mut exclusive = BTreeSet::new();
+        for (&key, &count) in &counts {
+            let Some(spec) = self.toppings.get(key) else {
+                violations.push(Violation::Unknown(key.to_string()));
+                continue;
+            };
+            if let Some(max) = spec.max.filter(|&max| count > max) {
+                violations.push(Violation::TooMany {
+                    topping: key.to_string(),
+                    max,
+                });
+            }
+            for other in &spec.excludes {
+                if counts.contains_key(other.as_str()) {
+                    exclusive.insert(if key < other.as_str() {
+                        (key, other.as_str())
+                    } else {
+                        (other.as_str(), key)
+                    });
+                }
+            }
+            if !spec.drinks.is_empty() && !spec.drinks.iter().any(|d| d == drink) {
+                violations.push(Violation::NotForDrink {
+                    topping: key.to_string(),
+                    drink: drink.to_string(),
+                });
+            }
+        }
+        violations.extend(
+            exclusive
+                .into_iter()
+                .map(|(topping, other)| Violation::Exclusive {
+                    topping: topping.to_string(),
+                    other: other.to_string(),
+                }),
+        );
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError(violations))
+        }
+    }
+}
+
+#[test]
+fn test_catalogue() {
+    let toml = ToppingCatalogue::from_toml(
+        "[toppings.caramel]\nname = \"caramel topping\"\nprice = \"USD 2.50\"\n",
+    )
+    .unwrap();
+    let json = ToppingCatalogue::from_json(
+        r#"{"toppings": {"caramel": {"name": "caramel topping", "price": "USD 2.5"}}}"#,
+    )
+    .unwrap();
+    assert_eq!(toml, json);
+    assert_eq!(toml.get("caramel").unwrap().name, "caramel topping");
+    assert_eq!(toml.get("milk"), Err(UnknownTopping("milk".to_string())));
+    assert!(ToppingCatalogue::from_toml("[toppings.milk]\nname = \"milk\"\nprice = 1").is_err());
+}
+
+#[test]
+fn test_validate() {
+    let catalogue = ToppingCatalogue::from_toml(include_str!("../toppings.toml")).unwrap();
+    assert_eq!(
+        catalogue.validate("coffee drink", &["milk", "mocha", "milk"]),
+        Ok(())
+    );
+    assert_eq!(
+        catalogue.validate(
+            "coffee drink",
+            &["whip", "mocha", "whip", "caramel", "boba", "soy"]
+        ),
+        Err(ValidationError(vec![
+            Violation::NotForDrink {
+                topping: "boba".to_string(),
+                drink: "coffee drink".to_string()
+            },
+            Violation::Unknown("soy".to_string()),
+            Violation::TooMany {
+                topping: "whip".to_string(),
+                max: 1
+            },
+            Violation::Exclusive {
+                topping: "caramel".to_string(),
+                other: "mocha".to_string()
+            },
+        ]))
+    );
+    assert_eq!(catalogue.validate("black tea", &["boba", "boba"]), Ok(()));
+}
@@ -1,12 +1,55 @@
-///! デザインパターンのデコレータパターンを実装してみる
+//! デザインパターンのデコレータパターンを実装してみる
+use crate::catalogue::{ToppingCatalogue, ValidationError};
+use crate::layers::{FromLayers, Layer, Layered, LayeredDrink, ToppingLayer};
+use crate::money::{Currency, Money, MoneyError, Rate};
+use crate::order::{Discount, Order};
+use crate::receipt::{Html, Json, PlainText, ReceiptLine, ReceiptRenderer, Thermal};
+use std::num::NonZeroU32;
+use std::path::Path;
 
 /// コーヒーショップのメニューが提供するインターフェース定義
-trait Item {
+pub trait Item {
     /// レシート明細  
-    /// 何をいくら分注文したのか
-    fn detail(&self) -> String;
-    /// 価格
-    fn price(&self) -> f32;
+    /// 何をいくら分注文したのかを、ベースのアイテムから順に1行ずつ返す  
+    /// サイズに合わせた価格が桁あふれすればエラーになる
+    fn receipt_lines(&self) -> Result<Vec<ReceiptLine>, MoneyError>;
+    /// 価格  
+    /// 通貨の異なるトッピングを重ねていたり、桁あふれしたりすればエラーになる
+    fn price(&self) -> Result<Money, MoneyError>;
+    /// ベースとなるドリンクのサイズ
+    fn size(&self) -> Size;
+    /// ベースとなるドリンクの名前
+    fn drink_name(&self) -> &str;
+}
+
+/// ドリンクのサイズ  
+/// サイズに合わせてドリンクとトッピングの価格が変わる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Size {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl Size {
+    /// Mediumの価格に対する割合
+    pub fn scale(&self) -> Rate {
+        match self {
+            Size::Small => Rate::basis_points(8000),
+            Size::Medium => Rate::basis_points(10000),
+            Size::Large => Rate::basis_points(12000),
+        }
+    }
+
+    /// レシートに表示する名前
+    pub fn name(&self) -> &'static str {
+        match self {
+            Size::Small => "small",
+            Size::Medium => "medium",
+            Size::Large => "large",
+        }
+    }
 }
 
 /// ドリンクメニューに追加するトッピング
@@ -15,78 +58,195 @@ trait Item {
 /// あくまでもインターフェースを定義したTraitを構造体が実装するのみ  
 /// なのでここからどのように具体的なトッピングの実体を定義し、  
 /// トッピング対象を如何にしてラッピング(=デコレート)するかが課題となった  
-struct Topping<T: Item> {
+pub struct Topping<T: Item> {
     /// トッピングを追加するベースとなる任意のアイテム
     item: T,
+    /// カタログでのトッピングのキー
+    key: String,
     /// トッピングの名前
     name: String,
     /// トッピングの価格
-    price: f32,
+    price: Money,
+}
+
+impl<T: Item> Topping<T> {
+    /// ベースとなるドリンクのサイズに合わせたトッピングの価格
+    fn sized_price(&self) -> Result<Money, MoneyError> {
+        self.price.percentage(self.item.size().scale())
+    }
 }
 
 /// トッピングに明細と価格を実装する
 impl<T: Item> Item for Topping<T> {
-    fn detail(&self) -> String {
-        // 自分自身の明細(トッピングメニュー名と価格)
-        let self_detail = format!("{}: ${}", self.name, self.price);
-        // ベースとなるアイテムに自分自身の明細を合成して返す
-        format!("{}\n{}", self.item.detail(), self_detail)
+    fn receipt_lines(&self) -> Result<Vec<ReceiptLine>, MoneyError> {
+        // ベースとなるアイテムの明細に自分自身の明細(トッピングメニュー名と価格)を合成して返す
+        let mut lines = self.item.receipt_lines()?;
+        let price = self.sized_price()?;
+        push_topping_line(&mut lines, &self.name, price);
+        Ok(lines)
     }
-    fn price(&self) -> f32 {
+    fn price(&self) -> Result<Money, MoneyError> {
         // ベースとなるアイテムの価格に自分自身の価格を加算して返す
-        self.item.price() + self.price
+        self.item.price()?.checked_add(self.sized_price()?)
+    }
+    fn size(&self) -> Size {
+        self.item.size()
+    }
+    fn drink_name(&self) -> &str {
+        self.item.drink_name()
+    }
+}
+
+/// ドリンクをベースにしたトッピングは、ベースの層に自分自身の層を重ねる
+impl<T: Layered> Layered for Topping<T> {
+    fn layers(&self) -> Vec<&dyn ToppingLayer> {
+        let mut layers = self.item.layers();
+        layers.push(self);
+        layers
+    }
+    fn into_layered(self) -> LayeredDrink {
+        let mut layered = self.item.into_layered();
+        layered.push_layer(Box::new(Layer {
+            key: self.key,
+            name: self.name,
+            price: self.price,
+        }));
+        layered
+    }
+}
+
+/// 型で重ねたトッピングも、それぞれが1つの層になる
+impl<T: Item> ToppingLayer for Topping<T> {
+    fn key(&self) -> &str {
+        &self.key
+    }
+    fn topping_name(&self) -> &str {
+        &self.name
+    }
+    fn unit_price(&self) -> Money {
+        self.price
+    }
+}
+
+/// 外側の層をこのトッピングにし、残りの層からベースのアイテムを組み立てる
+/// 層が足りなかったり余ったりすれば、取り出した層を戻して返す
+impl<T: FromLayers> FromLayers for Topping<T> {
+    fn from_layers(mut layered: LayeredDrink) -> Result<Topping<T>, LayeredDrink> {
+        let Some(layer) = layered.pop_layer() else {
+            return Err(layered);
+        };
+        match T::from_layers(layered) {
+            Ok(item) => Ok(Topping {
+                item,
+                key: layer.key().to_string(),
+                name: layer.topping_name().to_string(),
+                price: layer.unit_price(),
+            }),
+            Err(mut layered) => {
+                layered.push_layer(layer);
+                Err(layered)
+            }
+        }
+    }
+}
+
+/// トッピングの明細をlinesに加える
+/// 同じトッピングが既に重ねられていれば、行を増やさずに数量を増やす
+/// 先頭の行はベースのドリンクなのでまとめない
+pub fn push_topping_line(lines: &mut Vec<ReceiptLine>, name: &str, price: Money) {
+    let same = lines
+        .iter_mut()
+        .skip(1)
+        .find(|line| line.name == name && line.unit_price == price);
+    match same {
+        Some(line) => line.quantity += 1,
+        None => lines.push(ReceiptLine::new(name, price)),
     }
 }
 
 /// Topping構造体を継承するような従来のデコレータパターンでの実装はRustでは再現できない  
 /// そのため具体的なトッピングはTraitに定義する関数という形で表現してみた  
-trait ToppingMenu<T: Item> {
-    /// ミルクトッピングを追加したものを返す
-    fn milk(self) -> Topping<T>;
-    /// モカトッピングを追加したものを返す
-    fn mocha(self) -> Topping<T>;
+/// 以前はトッピングごとに関数を定義していたが、トッピングが増えるたびに  
+/// トレイトを書き換えずに済むよう、名前と価格は設定ファイルのカタログから引くようにした  
+pub trait ToppingMenu<T: Item> {
+    /// catalogueからkeyで指定されたトッピングを追加したものを返す  
+    /// カタログに無いトッピングや、カタログのルールを守らない組み合わせになるなら  
+    /// 守っていない全てのルールをValidationErrorで返す
+    fn add_topping(
+        self,
+        catalogue: &ToppingCatalogue,
+        key: &str,
+    ) -> Result<Topping<T>, ValidationError>;
 }
 
-/// Itemトレイトを実装する全ての型TにToppingMenuトレイトを実装する
+/// トッピングの層に分解できる全ての型TにToppingMenuトレイトを実装する
 /// これによりToppingとDrinkMenuが同一のトッピング追加インターフェースを持つようになる
 /// トッピング追加関数自身が新たなトッピングを返すことで、  
 /// builderパターンのようにチェーンメソッド形式でコードを記述できるようになる  
-/// 新しいトッピングが増えたらToppingMenuトレイトとここのデフォルト実装に追加するだけでよい  
-impl<T: Item> ToppingMenu<T> for T {
-    fn milk(self) -> Topping<T> {
-        Topping {
+/// 新しいトッピングが増えても設定ファイルに追加するだけでよい  
+impl<T: Layered> ToppingMenu<T> for T {
+    fn add_topping(
+        self,
+        catalogue: &ToppingCatalogue,
+        key: &str,
+    ) -> Result<Topping<T>, ValidationError> {
+        // 追加した後のトッピングの組み合わせ全体でルールを確かめる
+        let mut keys: Vec<&str> = self.layers().iter().map(|layer| layer.key()).collect();
+        keys.push(key);
+        catalogue.validate(self.drink_name(), &keys)?;
+        let spec = catalogue.get(key)?;
+        Ok(Topping {
             // 現在の自分自身をベースとして所有権ごと移す
             item: self,
-            // ミルクトッピング
-            name: "milk topping".to_string(),
-            // 価格は$3.3
-            price: 3.3,
-        }
-    }
-    fn mocha(self) -> Topping<T> {
-        Topping {
-            item: self,
-            name: "mocha topping".to_string(),
-            price: 4.5,
-        }
+            key: key.to_string(),
+            name: spec.name.clone(),
+            price: spec.price,
+        })
     }
 }
 
 /// トッピングのベースとなるドリンクメニュー
-struct DrinkMenu {
+pub struct DrinkMenu {
     /// ドリンクメニューの名前
-    name: String,
-    /// 価格
-    price: f32,
+    pub name: String,
+    /// Mediumサイズの価格
+    pub price: Money,
+    /// サイズ
+    pub size: Size,
 }
 
 /// Itemトレイトをドリンクメニューにも実装する
 impl Item for DrinkMenu {
-    fn detail(&self) -> String {
-        format!("{}: ${}", self.name.clone(), self.price)
+    fn receipt_lines(&self) -> Result<Vec<ReceiptLine>, MoneyError> {
+        let mut line = ReceiptLine::new(&self.name, self.price()?);
+        line.modifiers.push(self.size.name().to_string());
+        Ok(vec![line])
     }
-    fn price(&self) -> f32 {
-        self.price
+    fn price(&self) -> Result<Money, MoneyError> {
+        self.price.percentage(self.size.scale())
+    }
+    fn size(&self) -> Size {
+        self.size
+    }
+    fn drink_name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// ドリンクメニューはトッピングの層を持たないベースになる
+impl Layered for DrinkMenu {
+    fn layers(&self) -> Vec<&dyn ToppingLayer> {
+        Vec::new()
+    }
+    fn into_layered(self) -> LayeredDrink {
+        LayeredDrink::new(self)
+    }
+}
+
+/// 全ての層を取り出した後に、層が残っていなければドリンクに戻る
+impl FromLayers for DrinkMenu {
+    fn from_layers(layered: LayeredDrink) -> Result<DrinkMenu, LayeredDrink> {
+        layered.into_drink()
     }
 }
 
@@ -94,27 +254,197 @@ pub fn drinkmenu_decorator_sample() {
     // ベースとなるドリンクメニューを決める
     let coffee = DrinkMenu {
         name: "coffee drink".to_string(),
-        price: 10.0,
+        price: Money::new(1000, Currency::Usd),
+        size: Size::Medium,
+    };
+    // 注文できるトッピングは設定ファイルから読み込む
+    // 2つ目の引数でファイルを指定しなければ、ビルド時に埋め込んだtoppings.tomlを使う
+    let catalogue = match std::env::args().nth(2) {
+        Some(path) => ToppingCatalogue::load(Path::new(&path)),
+        None => ToppingCatalogue::from_toml(include_str!("../toppings.toml")),
+    };
+    let catalogue = match catalogue {
+        Ok(catalogue) => catalogue,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
     };
     // ベースに選んだドリンクメニューに好きなだけトッピングを追加する
-    // ToppingMenuトレイトの関数を任意の数だけ任意の順番でメソッドチェーン形式で呼び出せば良い
-    let item = coffee.milk().mocha();
+    // add_toppingを任意の数だけ任意の順番でメソッドチェーン形式で呼び出せば良い
+    let item = coffee
+        .add_topping(&catalogue, "milk")
+        .and_then(|item| item.add_topping(&catalogue, "mocha"))
+        .and_then(|item| item.add_topping(&catalogue, "milk"));
+    let item = match item {
+        Ok(item) => item,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    // 注文した後で気が変わっても、層に分解すればトッピングを削除したり差し替えたりできる
+    // 変更を終えたら、層の数に合う型の入れ子に戻す
+    let mut layered = item.into_layered();
+    if let Err(e) = layered.remove_topping("milk") {
+        println!("{}", e);
+        return;
+    }
+    if let Err(e) = layered.replace_topping(&catalogue, "mocha", "caramel") {
+        println!("{}", e);
+        return;
+    }
+    let item: Topping<Topping<DrinkMenu>> = match layered.into_static() {
+        Ok(item) => item,
+        Err(_) => {
+            println!("the number of toppings has changed");
+            return;
+        }
+    };
+    // トッピングの組み合わせやサイズが違うアイテムも1つの注文にまとめられる
+    let coffee = |size| DrinkMenu {
+        name: "coffee drink".to_string(),
+        price: Money::new(1000, Currency::Usd),
+        size,
+    };
+    let (member, tax) = match (Rate::percent(10), Rate::percent(8)) {
+        (Ok(member), Ok(tax)) => (member, tax),
+        (Err(e), _) | (_, Err(e)) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut order = Order::new(Currency::Usd)
+        .add(item, 1)
+        .add(coffee(Size::Large), 2)
+        .add(coffee(Size::Small), 1)
+        .with_discount(Discount::BuyNGetOne {
+            drink: "coffee drink".to_string(),
+            n: const { NonZeroU32::new(2).unwrap() },
+        })
+        .with_discount(Discount::Coupon {
+            code: "MEMBER".to_string(),
+            rate: member,
+        });
+    // 注文に入れた後でも、番号を指定してトッピングや個数を変更できる
+    // smallサイズを2杯にし、最初のアイテムのミルクを取り除いてキャラメルをホイップに差し替える
+    let small = order
+        .items()
+        .iter()
+        .position(|order_item| order_item.item.size() == Size::Small);
+    if let Some(index) = small {
+        order.set_quantity(index, 2);
+    }
+    if let Err(e) = order.remove_topping(0, "milk") {
+        println!("{}", e);
+        return;
+    }
+    if let Err(e) = order.replace_topping(0, &catalogue, "caramel", "whip") {
+        println!("{}", e);
+        return;
+    }
     // 確定した注文内容を確認する
-    /**
+    /*
      * `cargo run`で実行すると以下のような出力が得られる
+     * `cargo run -- thermal`のように引数でtext、thermal、json、htmlの表示形式を選べる
+     * `cargo run -- text toppings.json`のように2つ目の引数でトッピングの設定ファイルを選べる
      *
      * ```
      * wellcome, coffee shop!
+     * coffee drink: $10.00
+     *   medium
+     * whipped cream: $1.80
+     * coffee drink ×2: $24.00
+     *   large
+     * coffee drink ×2: $16.00
+     *   small
      * --------------------
-     * coffee drink: $10
-     * milk topping: $3.3
-     * mocha topping: $4.5
-     * --------------------
-     * total: $17.8
+     * subtotal: $51.80
+     * buy 2 get 1 free (coffee drink): -$8.00
+     * coupon MEMBER (10%): -$4.38
+     * tax (8%): $3.15
+     * total: $42.57
      * ```
      */
-    println!("--------------------");
-    println!("{}", item.detail());
-    println!("--------------------");
-    println!("total: ${}", item.price());
+    // 割引してから税を計算する
+    let receipt = order.receipt(tax);
+    let renderer: Box<dyn ReceiptRenderer> = match std::env::args().nth(1).as_deref() {
+        Some("thermal") => Box::new(Thermal::default()),
+        Some("json") => Box::new(Json),
+        Some("html") => Box::new(Html),
+        _ => Box::new(PlainText),
+    };
+    match receipt.and_then(|receipt| renderer.render(&receipt)) {
+        Ok(text) => println!("{}", text),
+        Err(e) => println!("{}", e),
+    }
+}
+
+#[test]
+fn test_add_topping() {
+    use crate::catalogue::Violation;
+
+    let catalogue = ToppingCatalogue::from_toml(include_str!("../toppings.toml")).unwrap();
+    let coffee = DrinkMenu {
+        name: "coffee drink".to_string(),
+        price: Money::new(1000, Currency::Usd),
+        size: Size::Medium,
+    };
+    let item = coffee
+        .add_topping(&catalogue, "caramel")
+        .and_then(|item| item.add_topping(&catalogue, "whip"))
+        .unwrap();
+    let item = item.add_topping(&catalogue, "caramel").unwrap();
+    let lines: Vec<_> = item
+        .receipt_lines()
+        .unwrap()
+        .iter()
+        .map(|line| (line.label(), line.unit_price.to_string()))
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            ("coffee drink".to_string(), "$10.00".to_string()),
+            ("caramel topping ×2".to_string(), "$2.50".to_string()),
+            ("whipped cream".to_string(), "$1.80".to_string()),
+        ]
+    );
+    assert_eq!(item.price(), Ok(Money::new(1680, Currency::Usd)));
+    let coffee = DrinkMenu {
+        name: "coffee drink".to_string(),
+        price: Money::new(1000, Currency::Usd),
+        size: Size::Medium,
+    };
+    assert_eq!(
+        coffee.add_topping(&catalogue, "wasabi").err(),
+        Some(ValidationError(vec![Violation::Unknown(
+            "wasabi".to_string()
+        )]))
+    );
+    // ホイップは1杯に1つまでで、タピオカはコーヒーに追加できない
+    let coffee = DrinkMenu {
+        name: "coffee drink".to_string(),
+        price: Money::new(1000, Currency::Usd),
+        size: Size::Medium,
+    };
+    let item = coffee.add_topping(&catalogue, "whip").unwrap();
+    assert_eq!(item.layers()[0].key(), "whip");
+    assert_eq!(
+        item.add_topping(&catalogue, "boba").err(),
+        Some(ValidationError(vec![Violation::NotForDrink {
+            topping: "boba".to_string(),
+            drink: "coffee drink".to_string()
+        }]))
+    );
+
+    let tea = DrinkMenu {
+        name: "green tea".to_string(),
+        price: Money::new(400, Currency::Jpy),
+        size: Size::Medium,
+    };
+    let item = tea.add_topping(&catalogue, "milk").unwrap();
+    assert_eq!(
+        item.price(),
+        Err(MoneyError::CurrencyMismatch(Currency::Jpy, Currency::Usd))
+    );
 }
@@ -0,0 +1,254 @@
+//! デコレートしたアイテムをトッピングの層の一覧として扱うモジュール
+//!
+//! `Topping<Topping<DrinkMenu>>`のように型で重ねたトッピングは後から変更できないので、
+//! ドリンクと`Vec<Box<dyn ToppingLayer>>`に分解して、注文を受けた後でも削除や差し替えができるようにする
+//! 変更を終えたら、層の数が同じ型の入れ子に戻せる
+use crate::catalogue::{ToppingCatalogue, ValidationError, Violation};
+use crate::decorator::{push_topping_line, DrinkMenu, Item, Size};
+use crate::money::{Money, MoneyError};
+use crate::receipt::ReceiptLine;
+
+/// ドリンクに重ねたトッピング1つ分の層
+pub trait ToppingLayer {
+    /// カタログでのトッピングのキー
+    fn key(&self) -> &str;
+    /// トッピングの名前
+    fn topping_name(&self) -> &str;
+    /// Mediumサイズでのトッピングの価格
+    fn unit_price(&self) -> Money;
+}
+
+/// ベースのアイテムから切り離したトッピングの層
+pub struct Layer {
+    pub key: String,
+    pub name: String,
+    pub price: Money,
+}
+
+impl ToppingLayer for Layer {
+    fn key(&self) -> &str {
+        &self.key
+    }
+    fn topping_name(&self) -> &str {
+        &self.name
+    }
+    fn unit_price(&self) -> Money {
+        self.price
+    }
+}
+
+/// ドリンクとトッピングの層の一覧
+/// 層はベースに近いものから順に並ぶ
+pub struct LayeredDrink {
+    drink: DrinkMenu,
+    layers: Vec<Box<dyn ToppingLayer>>,
+}
+
+impl LayeredDrink {
+    /// トッピングの無いドリンク
+    pub fn new(drink: DrinkMenu) -> LayeredDrink {
+        LayeredDrink {
+            drink,
+            layers: Vec::new(),
+        }
+    }
+
+    /// 一番外側にトッピングの層を重ねる
+    /// 既にルールを確かめたトッピングを分解するためのもので、ルールは確かめないのでクレートの外には公開しない
+    pub(crate) fn push_layer(&mut self, layer: Box<dyn ToppingLayer>) {
+        self.layers.push(layer);
+    }
+
+    /// 一番外側のトッピングの層を取り出す
+    /// 型の入れ子に戻すためのもので、push_layerと対になる
+    pub(crate) fn pop_layer(&mut self) -> Option<Box<dyn ToppingLayer>> {
+        self.layers.pop()
+    }
+
+    /// トッピングの層が残っていなければドリンクに戻す
+    /// 層が残っていればそのまま返す
+    pub fn into_drink(self) -> Result<DrinkMenu, LayeredDrink> {
+        if self.layers.is_empty() {
+            Ok(self.drink)
+        } else {
+            Err(self)
+        }
+    }
+
+    /// ドリンクのサイズに合わせた層の価格
+    fn sized_price(&self, layer: &dyn ToppingLayer) -> Result<Money, MoneyError> {
+        layer.unit_price().percentage(self.drink.size.scale())
+    }
+
+    /// 最後に重ねたkeyのトッピングの位置
+    fn position(&self, key: &str) -> Result<usize, ValidationError> {
+        self.layers
+            .iter()
+            .rposition(|layer| layer.key() == key)
+            .ok_or_else(|| ValidationError(vec![Violation::NotAdded(key.to_string())]))
+    }
+
+    /// 最後に重ねたkeyのトッピングを取り除いて返す
+    /// keyのトッピングが無ければ、replace_toppingと同じくNotAddedのValidationErrorを返す
+    pub fn remove_topping(&mut self, key: &str) -> Result<Box<dyn ToppingLayer>, ValidationError> {
+        let index = self.position(key)?;
+        Ok(self.layers.remove(index))
+    }
+
+    /// 最後に重ねたfromのトッピングを、同じ位置のままcatalogueのtoのトッピングに差し替える
+    /// 差し替えた後の組み合わせがルールを守らなければ、何も変更せずにValidationErrorを返す
+    pub fn replace_topping(
+        &mut self,
+        catalogue: &ToppingCatalogue,
+        from: &str,
+        to: &str,
+    ) -> Result<(), ValidationError> {
+        let index = self.position(from)?;
+        let mut keys: Vec<&str> = self.layers.iter().map(|layer| layer.key()).collect();
+        keys[index] = to;
+        catalogue.validate(&self.drink.name, &keys)?;
+        let spec = catalogue.get(to)?;
+        self.layers[index] = Box::new(Layer {
+            key: to.to_string(),
+            name: spec.name.clone(),
+            price: spec.price,
+        });
+        Ok(())
+    }
+
+    /// 層の数が同じ型の入れ子に戻す
+    /// 層の数が違えばそのまま返す
+    pub fn into_static<T: FromLayers>(self) -> Result<T, LayeredDrink> {
+        T::from_layers(self)
+    }
+}
+
+impl Item for LayeredDrink {
+    fn receipt_lines(&self) -> Result<Vec<ReceiptLine>, MoneyError> {
+        let mut lines = self.drink.receipt_lines()?;
+        for layer in &self.layers {
+            push_topping_line(
+                &mut lines,
+                layer.topping_name(),
+                self.sized_price(layer.as_ref())?,
+            );
+        }
+        Ok(lines)
+    }
+    fn price(&self) -> Result<Money, MoneyError> {
+        let mut price = self.drink.price()?;
+        for layer in &self.layers {
+            price = price.checked_add(self.sized_price(layer.as_ref())?)?;
+        }
+        Ok(price)
+    }
+    fn size(&self) -> Size {
+        self.drink.size
+    }
+    fn drink_name(&self) -> &str {
+        &self.drink.name
+    }
+}
+
+impl Layered for LayeredDrink {
+    fn layers(&self) -> Vec<&dyn ToppingLayer> {
+        self.layers.iter().map(|layer| layer.as_ref()).collect()
+    }
+    fn into_layered(self) -> LayeredDrink {
+        self
+    }
+}
+
+/// DrinkMenuをベースにした、トッピングの層に分解できるアイテム
+/// 層はドリンクに重ねるものなので、Itemとは分けてドリンクをベースにしたアイテムだけに実装する
+pub trait Layered: Item {
+    /// 追加したトッピングの層  
+    /// ベースに近いものから順に、重ねた数だけ返す
+    fn layers(&self) -> Vec<&dyn ToppingLayer>;
+    /// ドリンクとトッピングの層の一覧に分解する  
+    /// 分解すると後からトッピングを削除したり差し替えたりできる
+    fn into_layered(self) -> LayeredDrink
+    where
+        Self: Sized;
+}
+
+/// トッピングの層の一覧から組み立てられる、型で重ねたアイテム
+pub trait FromLayers: Layered + Sized {
+    /// layeredの外側の層から取り出して組み立てる
+    /// 層の数が型と合わなければ、取り出した層を戻してlayeredをそのまま返す
+    fn from_layers(layered: LayeredDrink) -> Result<Self, LayeredDrink>;
+}
+
+#[test]
+fn test_layers() {
+    use crate::decorator::{Topping, ToppingMenu};
+    use crate::money::Currency;
+
+    let catalogue = ToppingCatalogue::from_toml(include_str!("../toppings.toml")).unwrap();
+    let coffee = DrinkMenu {
+        name: "coffee drink".to_string(),
+        price: Money::new(1000, Currency::Usd),
+        size: Size::Large,
+    };
+    let item = coffee
+        .add_topping(&catalogue, "milk")
+        .and_then(|item| item.add_topping(&catalogue, "mocha"))
+        .and_then(|item| item.add_topping(&catalogue, "milk"))
+        .unwrap();
+    let keys = |item: &dyn Layered| -> Vec<String> {
+        item.layers()
+            .iter()
+            .map(|layer| layer.key().to_string())
+            .collect()
+    };
+    assert_eq!(keys(&item), vec!["milk", "mocha", "milk"]);
+    let price = item.price();
+    let lines = item.receipt_lines();
+
+    // 分解しても明細と価格は変わらない
+    let mut drink = item.into_layered();
+    assert_eq!(drink.price(), price);
+    assert_eq!(drink.receipt_lines(), lines);
+
+    assert_eq!(
+        drink.remove_topping("milk").map(|l| l.unit_price()),
+        Ok(Money::new(330, Currency::Usd))
+    );
+    assert_eq!(
+        drink.remove_topping("whip").err(),
+        Some(ValidationError(vec![Violation::NotAdded(
+            "whip".to_string()
+        )]))
+    );
+    // モカとキャラメルは一緒に追加できないので、差し替えなら追加できる
+    assert_eq!(
+        drink.replace_topping(&catalogue, "milk", "caramel"),
+        Err(ValidationError(vec![Violation::Exclusive {
+            topping: "caramel".to_string(),
+            other: "mocha".to_string()
+        }]))
+    );
+    drink
+        .replace_topping(&catalogue, "mocha", "caramel")
+        .unwrap();
+    assert_eq!(
+        drink.replace_topping(&catalogue, "mocha", "whip"),
+        Err(ValidationError(vec![Violation::NotAdded(
+            "mocha".to_string()
+        )]))
+    );
+    assert_eq!(keys(&drink), vec!["milk", "caramel"]);
+    // (10.00 + 3.30 + 2.50) × 1.2
+    assert_eq!(drink.price(), Ok(Money::new(1896, Currency::Usd)));
+
+    // 層の数が違う型には戻せず、層を失わずにそのまま返る
+    let drink = drink.into_static::<Topping<DrinkMenu>>().err().unwrap();
+    let drink = drink
+        .into_static::<Topping<Topping<Topping<DrinkMenu>>>>()
+        .err()
+        .unwrap();
+    assert_eq!(keys(&drink), vec!["milk", "caramel"]);
+    let item: Topping<Topping<DrinkMenu>> = drink.into_static().ok().unwrap();
+    assert_eq!(keys(&item), vec!["milk", "caramel"]);
+    assert_eq!(item.price(), Ok(Money::new(1896, Currency::Usd)));
+}
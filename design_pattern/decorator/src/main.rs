@@ -1,4 +1,9 @@
+mod catalogue;
 mod decorator;
+mod layers;
+mod money;
+mod order;
+mod receipt;
 use decorator::*;
 
 fn main() {
@@ -0,0 +1,350 @@
+//! 金額を浮動小数点数ではなく通貨の最小単位の整数で扱うモジュール
+//!
+//! f32で価格を足し合わせると誤差が溜まるので、セントや円などの最小単位で数える
+//! 異なる通貨同士の計算と桁あふれはエラーにする
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// 通貨
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Currency {
+    Usd,
+    Eur,
+    Gbp,
+    Jpy,
+}
+
+impl Currency {
+    /// ISO 4217の通貨コード
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+            Currency::Gbp => "GBP",
+            Currency::Jpy => "JPY",
+        }
+    }
+
+    /// 通貨記号
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Currency::Usd => "$",
+            Currency::Eur => "€",
+            Currency::Gbp => "£",
+            Currency::Jpy => "¥",
+        }
+    }
+
+    /// 最小単位が1単位の何桁目か
+    /// ドルはセントまでなので2、円は1円が最小なので0
+    pub fn exponent(&self) -> u32 {
+        match self {
+            Currency::Jpy => 0,
+            _ => 2,
+        }
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Currency, MoneyError> {
+        match code {
+            "USD" => Ok(Currency::Usd),
+            "EUR" => Ok(Currency::Eur),
+            "GBP" => Ok(Currency::Gbp),
+            "JPY" => Ok(Currency::Jpy),
+            _ => Err(MoneyError::Parse(format!("unknown currency '{}'", code))),
+        }
+    }
+}
+
+/// 税率や割引率
+/// 1%の100分の1(ベーシスポイント)単位で表す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    basis_points: u32,
+}
+
+impl Rate {
+    /// percent%の率
+    /// ベーシスポイントで表せないほど大きければエラーにする
+    pub fn percent(percent: u32) -> Result<Rate, MoneyError> {
+        let basis_points = percent.checked_mul(100).ok_or(MoneyError::Overflow)?;
+        Ok(Rate { basis_points })
+    }
+
+    /// basis_pointsベーシスポイントの率
+    pub fn basis_points(basis_points: u32) -> Rate {
+        Rate { basis_points }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (whole, fraction) = (self.basis_points / 100, self.basis_points % 100);
+        if fraction == 0 {
+            write!(f, "{}%", whole)
+        } else {
+            write!(f, "{}.{:02}%", whole, fraction)
+        }
+    }
+}
+
+/// 金額の計算に失敗した理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    /// 異なる通貨の金額を計算しようとした
+    CurrencyMismatch(Currency, Currency),
+    /// 計算結果が表せる範囲を超えた
+    Overflow,
+    /// 金額の文字列を解釈できなかった
+    Parse(String),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch(a, b) => {
+                write!(f, "cannot combine {} and {}", a.code(), b.code())
+            }
+            MoneyError::Overflow => write!(f, "the amount is too large"),
+            MoneyError::Parse(message) => write!(f, "invalid amount: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+/// 金額
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    /// 通貨の最小単位で数えた金額
+    minor: i64,
+    currency: Currency,
+}
+
+impl Money {
+    /// 最小単位でminor分の金額
+    /// ドルならMoney::new(1050, Currency::Usd)が$10.50になる
+    pub fn new(minor: i64, currency: Currency) -> Money {
+        Money { minor, currency }
+    }
+
+    /// 通貨が同じか確かめてから、2つの金額の最小単位の値にopを適用する
+    fn combine(self, other: Money, op: fn(i64, i64) -> Option<i64>) -> Result<Money, MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(self.currency, other.currency));
+        }
+        let minor = op(self.minor, other.minor).ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor, self.currency))
+    }
+
+    /// currencyの0
+    pub fn zero(currency: Currency) -> Money {
+        Money::new(0, currency)
+    }
+
+    /// 最小単位で数えた金額
+    pub fn minor(&self) -> i64 {
+        self.minor
+    }
+
+    /// 符号を反転した金額
+    /// レシートに割引額を負の金額として載せるのに使う
+    /// i64::MINは反転すると表せないのでエラーにする
+    pub fn negated(self) -> Result<Money, MoneyError> {
+        let minor = self.minor.checked_neg().ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor, self.currency))
+    }
+
+    /// 2つの金額の和
+    pub fn checked_add(self, other: Money) -> Result<Money, MoneyError> {
+        self.combine(other, i64::checked_add)
+    }
+
+    /// 2つの金額の差
+    pub fn checked_sub(self, other: Money) -> Result<Money, MoneyError> {
+        self.combine(other, i64::checked_sub)
+    }
+
+    /// 金額のcount倍
+    pub fn checked_mul(self, count: u32) -> Result<Money, MoneyError> {
+        let minor = self
+            .minor
+            .checked_mul(i64::from(count))
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::new(minor, self.currency))
+    }
+
+    /// 金額のrate分
+    /// 最小単位未満は四捨五入する
+    /// 100%を超える率で表せない金額になる場合はエラーにする
+    pub fn percentage(self, rate: Rate) -> Result<Money, MoneyError> {
+        let scaled = i128::from(self.minor) * i128::from(rate.basis_points);
+        let half = if scaled < 0 { -5000 } else { 5000 };
+        let minor = i64::try_from((scaled + half) / 10000).map_err(|_| MoneyError::Overflow)?;
+        Ok(Money::new(minor, self.currency))
+    }
+
+    /// rateの税を加えた金額
+    pub fn with_tax(self, rate: Rate) -> Result<Money, MoneyError> {
+        self.checked_add(self.percentage(rate)?)
+    }
+
+    /// 符号と通貨記号を除いた、通貨の桁数に合わせた10進数の金額
+    fn unsigned_decimal(&self) -> String {
+        let exponent = self.currency.exponent();
+        let unit = 10u64.pow(exponent);
+        let amount = self.minor.unsigned_abs();
+        if exponent == 0 {
+            return amount.to_string();
+        }
+        format!(
+            "{}.{:0width$}",
+            amount / unit,
+            amount % unit,
+            width = exponent as usize
+        )
+    }
+
+    /// 負の金額なら`-`を返す
+    fn sign(&self) -> &'static str {
+        if self.minor < 0 {
+            "-"
+        } else {
+            ""
+        }
+    }
+}
+
+/// 通貨記号を付け、通貨の桁数に合わせて表示する
+/// 例えば$10.00や¥500になる
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.sign(),
+            self.currency.symbol(),
+            self.unsigned_decimal()
+        )
+    }
+}
+
+/// `USD 3.30`のような通貨コードと10進数の金額から金額を作る
+/// 通貨の桁数より細かい金額や、`USD 1.`のように小数点の後に数字の無い金額は誤りとする
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    fn from_str(text: &str) -> Result<Money, MoneyError> {
+        let invalid = || MoneyError::Parse(format!("'{}' is not like 'USD 3.30'", text));
+        let (code, amount) = text.trim().split_once(' ').ok_or_else(invalid)?;
+        let currency: Currency = code.parse()?;
+        let (negative, amount) = match amount.trim().strip_prefix('-') {
+            Some(amount) => (true, amount),
+            None => (false, amount.trim()),
+        };
+        let (whole, fraction) = match amount.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (amount, None),
+        };
+        let exponent = currency.exponent() as usize;
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+        if !digits(whole) || !fraction.is_none_or(|f| digits(f) && f.len() <= exponent) {
+            return Err(invalid());
+        }
+        let fraction = fraction.unwrap_or("");
+        let fraction = format!("{:0<width$}", fraction, width = exponent);
+        let minor: i64 = format!("{}{}", whole, fraction)
+            .parse()
+            .map_err(|_| MoneyError::Overflow)?;
+        Ok(Money::new(if negative { -minor } else { minor }, currency))
+    }
+}
+
+/// 設定ファイルでは`price = "USD 3.30"`のように文字列で書く
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// 設定ファイルと同じく`"USD 3.30"`のような文字列に書き出す
+impl Serialize for Money {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!(
+            "{} {}{}",
+            self.currency.code(),
+            self.sign(),
+            self.unsigned_decimal()
+        ))
+    }
+}
+
+#[test]
+fn test_money() {
+    let price: Money = "USD 10".parse().unwrap();
+    assert_eq!(price, Money::new(1000, Currency::Usd));
+    assert_eq!(price.to_string(), "$10.00");
+    // f32では0.1+0.2が0.3にならないが、最小単位の整数なら誤差が無い
+    let sum = Money::new(10, Currency::Usd)
+        .checked_add(Money::new(20, Currency::Usd))
+        .unwrap();
+    assert_eq!(sum, "USD 0.30".parse().unwrap());
+    assert_eq!("JPY 500".parse::<Money>().unwrap().to_string(), "¥500");
+    assert_eq!(Money::new(-5, Currency::Eur).to_string(), "-€0.05");
+    assert_eq!(
+        serde_json::to_string(&Money::new(-5, Currency::Eur)).unwrap(),
+        r#""EUR -0.05""#
+    );
+
+    assert!("USD 1.234".parse::<Money>().is_err());
+    assert!("USD 1.".parse::<Money>().is_err());
+    assert!("USD .5".parse::<Money>().is_err());
+    assert_eq!("USD 1.5".parse(), Ok(Money::new(150, Currency::Usd)));
+    assert!("JPY 1.5".parse::<Money>().is_err());
+    assert!("XYZ 1".parse::<Money>().is_err());
+    assert_eq!(
+        price.checked_add(Money::new(1, Currency::Jpy)),
+        Err(MoneyError::CurrencyMismatch(Currency::Usd, Currency::Jpy))
+    );
+    assert_eq!(
+        Money::new(i64::MAX, Currency::Usd).checked_add(Money::new(1, Currency::Usd)),
+        Err(MoneyError::Overflow)
+    );
+
+    let subtotal = Money::new(1780, Currency::Usd);
+    let percent = |percent| Rate::percent(percent).unwrap();
+    assert_eq!(
+        subtotal.percentage(percent(8)),
+        Ok(Money::new(142, Currency::Usd))
+    );
+    assert_eq!(
+        subtotal.with_tax(percent(10)).unwrap(),
+        Money::new(1958, Currency::Usd)
+    );
+    assert_eq!(
+        subtotal.percentage(percent(120)),
+        Ok(Money::new(2136, Currency::Usd))
+    );
+    assert_eq!(percent(8).to_string(), "8%");
+    assert_eq!(Rate::basis_points(850).to_string(), "8.50%");
+    // 表せない金額や率は最大値に丸めずにエラーにする
+    assert_eq!(
+        Money::new(i64::MAX, Currency::Usd).percentage(percent(120)),
+        Err(MoneyError::Overflow)
+    );
+    assert_eq!(
+        Money::new(i64::MAX, Currency::Usd).with_tax(percent(8)),
+        Err(MoneyError::Overflow)
+    );
+    assert_eq!(Rate::percent(u32::MAX), Err(MoneyError::Overflow));
+    assert_eq!(price.negated(), Ok(Money::new(-1000, Currency::Usd)));
+    assert_eq!(
+        Money::new(i64::MIN, Currency::Usd).negated(),
+        Err(MoneyError::Overflow)
+    );
+}
@@ -0,0 +1,436 @@
+//! 複数のアイテムをまとめた注文を扱うモジュール
+//!
+//! トッピングの組み合わせが違うアイテムは型も違うので、層の一覧に分解して1つの注文に並べる
+//! 分解しておけば、注文に入れた後でもトッピングを削除したり差し替えたりできる
+//! 割引は注文全体に対して計算し、割引した後の金額に税をかける
+use crate::catalogue::{ToppingCatalogue, ValidationError};
+use crate::decorator::Item;
+use crate::layers::{Layered, LayeredDrink, ToppingLayer};
+use crate::money::{Currency, Money, MoneyError, Rate};
+use crate::receipt::{Receipt, ReceiptLine};
+use std::fmt;
+use std::num::NonZeroU32;
+
+/// 注文の1行分のアイテムと個数
+pub struct OrderItem {
+    /// トッピングを追加したアイテムを層に分解したもの
+    pub item: LayeredDrink,
+    /// 個数
+    pub quantity: u32,
+}
+
+/// 注文全体にかける割引
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Discount {
+    /// 金額をrate分割り引くクーポン
+    Coupon { code: String, rate: Rate },
+    /// drinkをn杯買うごとに1杯無料にする
+    /// 無料になるのはトッピングを含めた価格が安いものから
+    /// nが0だと全てが無料になってしまうので、0は作れない型にする
+    BuyNGetOne { drink: String, n: NonZeroU32 },
+}
+
+impl Discount {
+    /// レシートに表示する割引の名前
+    fn label(&self) -> String {
+        match self {
+            Discount::Coupon { code, rate } => format!("coupon {} ({})", code, rate),
+            Discount::BuyNGetOne { drink, n } => format!("buy {} get 1 free ({})", n, drink),
+        }
+    }
+}
+
+/// 注文に入れたアイテムを変更できなかった理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderError {
+    /// 注文に無い番号のアイテムを変更しようとした
+    NoItem(usize),
+    /// 変更した後のトッピングがルールを守らない
+    Invalid(ValidationError),
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderError::NoItem(index) => write!(f, "the order has no item #{}", index),
+            OrderError::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+impl From<ValidationError> for OrderError {
+    fn from(e: ValidationError) -> OrderError {
+        OrderError::Invalid(e)
+    }
+}
+
+/// 注文の金額の内訳
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Totals {
+    /// 割引前の合計
+    pub subtotal: Money,
+    /// 割引の名前と割り引いた金額
+    pub discounts: Vec<(String, Money)>,
+    /// 税額
+    pub tax: Money,
+    /// 割引と税を反映した支払う金額
+    pub total: Money,
+}
+
+/// 複数のアイテムをまとめた注文
+pub struct Order {
+    /// 注文の通貨
+    currency: Currency,
+    items: Vec<OrderItem>,
+    discounts: Vec<Discount>,
+}
+
+impl Order {
+    /// currencyで支払う空の注文を作る
+    pub fn new(currency: Currency) -> Order {
+        Order {
+            currency,
+            items: Vec::new(),
+            discounts: Vec::new(),
+        }
+    }
+
+    /// itemをquantity個追加した注文を返す
+    pub fn add(mut self, item: impl Layered, quantity: u32) -> Order {
+        self.items.push(OrderItem {
+            item: item.into_layered(),
+            quantity,
+        });
+        self
+    }
+
+    /// 追加した順に並んだ、注文のアイテムと個数
+    pub fn items(&self) -> &[OrderItem] {
+        &self.items
+    }
+
+    /// index番目のアイテム
+    /// アイテムが無ければNoItemを返す
+    fn item_mut(&mut self, index: usize) -> Result<&mut LayeredDrink, OrderError> {
+        self.items
+            .get_mut(index)
+            .map(|order_item| &mut order_item.item)
+            .ok_or(OrderError::NoItem(index))
+    }
+
+    /// index番目のアイテムから、最後に重ねたkeyのトッピングを取り除いて返す
+    /// アイテムかトッピングが無ければ、何も変更せずにOrderErrorを返す
+    pub fn remove_topping(
+        &mut self,
+        index: usize,
+        key: &str,
+    ) -> Result<Box<dyn ToppingLayer>, OrderError> {
+        Ok(self.item_mut(index)?.remove_topping(key)?)
+    }
+
+    /// index番目のアイテムの最後に重ねたfromのトッピングを、catalogueのtoのトッピングに差し替える
+    /// アイテムが無いか、差し替えた後の組み合わせがルールを守らなければ、何も変更せずにOrderErrorを返す
+    pub fn replace_topping(
+        &mut self,
+        index: usize,
+        catalogue: &ToppingCatalogue,
+        from: &str,
+        to: &str,
+    ) -> Result<(), OrderError> {
+        Ok(self.item_mut(index)?.replace_topping(catalogue, from, to)?)
+    }
+
+    /// index番目のアイテムの個数をquantityに変えて、変える前の個数を返す
+    /// アイテムが無ければNoneを返す
+    pub fn set_quantity(&mut self, index: usize, quantity: u32) -> Option<u32> {
+        let order_item = self.items.get_mut(index)?;
+        Some(std::mem::replace(&mut order_item.quantity, quantity))
+    }
+
+    /// 割引を追加した注文を返す
+    /// 割引は追加した順に、それまでの割引を引いた金額に対して計算する
+    pub fn with_discount(mut self, discount: Discount) -> Order {
+        self.discounts.push(discount);
+        self
+    }
+
+    /// 各アイテムの明細に個数を掛けた、注文全体の明細
+    pub fn receipt_lines(&self) -> Result<Vec<ReceiptLine>, MoneyError> {
+        let mut lines = Vec::new();
+        for order_item in &self.items {
+            for mut line in order_item.item.receipt_lines()? {
+                line.quantity = line
+                    .quantity
+                    .checked_mul(order_item.quantity)
+                    .ok_or(MoneyError::Overflow)?;
+                lines.push(line);
+            }
+        }
+        Ok(lines)
+    }
+
+    /// 割引前の合計
+    pub fn subtotal(&self) -> Result<Money, MoneyError> {
+        let mut subtotal = Money::zero(self.currency);
+        for order_item in &self.items {
+            let amount = order_item.item.price()?.checked_mul(order_item.quantity)?;
+            subtotal = subtotal.checked_add(amount)?;
+        }
+        Ok(subtotal)
+    }
+
+    /// 1つの割引で割り引く金額
+    /// remainingはそれまでの割引を引いた金額
+    fn discount_amount(&self, discount: &Discount, remaining: Money) -> Result<Money, MoneyError> {
+        match discount {
+            Discount::Coupon { rate, .. } => remaining.percentage(*rate),
+            Discount::BuyNGetOne { drink, n } => {
+                // 1杯ずつ並べずに、価格と杯数の組を安い順に並べて無料の杯数を割り当てる
+                let mut prices = Vec::new();
+                for order_item in &self.items {
+                    if order_item.item.drink_name() == drink && order_item.quantity > 0 {
+                        prices.push((order_item.item.price()?, order_item.quantity));
+                    }
+                }
+                prices.sort_by_key(|(price, _)| price.minor());
+                let cups: u64 = prices
+                    .iter()
+                    .map(|(_, quantity)| u64::from(*quantity))
+                    .sum();
+                let mut free = cups / (u64::from(n.get()) + 1);
+                let mut amount = Money::zero(self.currency);
+                for (price, quantity) in prices {
+                    if free == 0 {
+                        break;
+                    }
+                    // freeがquantityより小さければu32に収まる
+                    let taken = u32::try_from(free).map_or(quantity, |free| free.min(quantity));
+                    amount = amount.checked_add(price.checked_mul(taken)?)?;
+                    free -= u64::from(taken);
+                }
+                Ok(amount)
+            }
+        }
+    }
+
+    /// taxの税率で、割引と税を含めた注文の金額を計算する
+    /// 割引は合計を超えない
+    pub fn totals(&self, tax: Rate) -> Result<Totals, MoneyError> {
+        let subtotal = self.subtotal()?;
+        let mut remaining = subtotal;
+        let mut discounts = Vec::new();
+        for discount in &self.discounts {
+            let amount = self.discount_amount(discount, remaining)?;
+            let amount = if amount.minor() > remaining.minor() {
+                remaining
+            } else {
+                amount
+            };
+            remaining = remaining.checked_sub(amount)?;
+            discounts.push((discount.label(), amount));
+        }
+        Ok(Totals {
+            subtotal,
+            discounts,
+            tax: remaining.percentage(tax)?,
+            total: remaining.with_tax(tax)?,
+        })
+    }
+
+    /// 明細と、小計、割引、税、合計の集計行からなるレシート
+    pub fn receipt(&self, tax: Rate) -> Result<Receipt, MoneyError> {
+        let totals = self.totals(tax)?;
+        let mut receipt =
+            Receipt::new(self.receipt_lines()?).with_summary("subtotal", totals.subtotal);
+        for (label, amount) in &totals.discounts {
+            receipt = receipt.with_summary(label, amount.negated()?);
+        }
+        Ok(receipt
+            .with_summary(&format!("tax ({})", tax), totals.tax)
+            .with_summary("total", totals.total))
+    }
+}
+
+#[test]
+fn test_order() {
+    use crate::catalogue::{ToppingCatalogue, Violation};
+    use crate::decorator::{DrinkMenu, Size, ToppingMenu};
+
+    let catalogue = ToppingCatalogue::from_toml(include_str!("../toppings.toml")).unwrap();
+    let percent = |percent| Rate::percent(percent).unwrap();
+    let coffee = |size| DrinkMenu {
+        name: "coffee drink".to_string(),
+        price: Money::new(1000, Currency::Usd),
+        size,
+    };
+    let tea = DrinkMenu {
+        name: "black tea".to_string(),
+        price: Money::new(500, Currency::Usd),
+        size: Size::Small,
+    };
+    // Largeはドリンクもトッピングも1.2倍になる
+    let large = coffee(Size::Large).add_topping(&catalogue, "milk").unwrap();
+    assert_eq!(large.price(), Ok(Money::new(1596, Currency::Usd)));
+
+    let order = Order::new(Currency::Usd)
+        .add(large, 1)
+        .add(coffee(Size::Medium), 2)
+        .add(tea, 3);
+    let lines: Vec<_> = order
+        .receipt_lines()
+        .unwrap()
+        .iter()
+        .map(|line| line.label())
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            "coffee drink",
+            "milk topping",
+            "coffee drink ×2",
+            "black tea ×3"
+        ]
+    );
+    // 15.96 + 10.00 × 2 + 4.00 × 3
+    assert_eq!(order.subtotal(), Ok(Money::new(4796, Currency::Usd)));
+
+    // coffee drinkは3杯なので最も安い$10.00の1杯が無料になり、残りの$37.96から10%引く
+    let order = order
+        .with_discount(Discount::BuyNGetOne {
+            drink: "coffee drink".to_string(),
+            n: NonZeroU32::new(2).unwrap(),
+        })
+        .with_discount(Discount::Coupon {
+            code: "WELCOME".to_string(),
+            rate: percent(10),
+        });
+    let totals = order.totals(percent(8)).unwrap();
+    assert_eq!(
+        totals.discounts,
+        vec![
+            (
+                "buy 2 get 1 free (coffee drink)".to_string(),
+                Money::new(1000, Currency::Usd)
+            ),
+            (
+                "coupon WELCOME (10%)".to_string(),
+                Money::new(380, Currency::Usd)
+            ),
+        ]
+    );
+    assert_eq!(totals.tax, Money::new(273, Currency::Usd));
+    assert_eq!(totals.total, Money::new(3689, Currency::Usd));
+    let receipt = order.receipt(percent(8)).unwrap();
+    assert_eq!(
+        receipt.summary[1],
+        (
+            "buy 2 get 1 free (coffee drink)".to_string(),
+            Money::new(-1000, Currency::Usd)
+        )
+    );
+
+    // 割引は合計を超えない
+    let order = Order::new(Currency::Usd)
+        .add(coffee(Size::Small), 1)
+        .with_discount(Discount::Coupon {
+            code: "FREE".to_string(),
+            rate: percent(150),
+        });
+    let totals = order.totals(percent(8)).unwrap();
+    assert_eq!(totals.discounts[0].1, Money::new(800, Currency::Usd));
+    assert_eq!(totals.total, Money::zero(Currency::Usd));
+
+    // 注文に入れた後のアイテムも、番号を指定して変更できる
+    let mut order = Order::new(Currency::Usd)
+        .add(
+            coffee(Size::Medium)
+                .add_topping(&catalogue, "mocha")
+                .unwrap(),
+            1,
+        )
+        .add(coffee(Size::Small), 1);
+    let keys: Vec<_> = order.items()[0]
+        .item
+        .layers()
+        .iter()
+        .map(|layer| layer.key().to_string())
+        .collect();
+    assert_eq!(keys, vec!["mocha"]);
+    order
+        .replace_topping(0, &catalogue, "mocha", "caramel")
+        .unwrap();
+    assert_eq!(
+        order.replace_topping(2, &catalogue, "mocha", "caramel"),
+        Err(OrderError::NoItem(2))
+    );
+    assert_eq!(
+        order.replace_topping(0, &catalogue, "milk", "caramel"),
+        Err(OrderError::Invalid(ValidationError(vec![
+            Violation::NotAdded("milk".to_string())
+        ])))
+    );
+    assert_eq!(order.set_quantity(1, 3), Some(1));
+    assert_eq!(order.set_quantity(2, 3), None);
+    // 10.00 + 2.50 + 8.00 × 3
+    assert_eq!(order.subtotal(), Ok(Money::new(3650, Currency::Usd)));
+    assert_eq!(
+        order
+            .remove_topping(0, "caramel")
+            .map(|layer| layer.unit_price()),
+        Ok(Money::new(250, Currency::Usd))
+    );
+    assert!(matches!(
+        order.remove_topping(0, "caramel"),
+        Err(OrderError::Invalid(_))
+    ));
+    assert!(matches!(
+        order.remove_topping(2, "milk"),
+        Err(OrderError::NoItem(2))
+    ));
+    assert_eq!(order.subtotal(), Ok(Money::new(3400, Currency::Usd)));
+
+    // 杯数が多くても1杯ずつ並べずに、安い価格の杯から無料にする
+    let order = Order::new(Currency::Usd)
+        .add(coffee(Size::Medium), u32::MAX)
+        .add(coffee(Size::Small), 3)
+        .add(coffee(Size::Medium), u32::MAX)
+        .with_discount(Discount::BuyNGetOne {
+            drink: "coffee drink".to_string(),
+            n: NonZeroU32::MAX,
+        });
+    // u32::MAX × 2 + 3杯のうち2杯が無料になり、$8.00のsmallサイズから割り当てる
+    assert_eq!(
+        order.discount_amount(&order.discounts[0], Money::zero(Currency::Usd)),
+        Ok(Money::new(1600, Currency::Usd))
+    );
+
+    // サイズに合わせた価格が表せなければ、最大の金額に丸めずにエラーにする
+    let order = Order::new(Currency::Usd).add(
+        DrinkMenu {
+            name: "coffee drink".to_string(),
+            price: Money::new(i64::MAX, Currency::Usd),
+            size: Size::Large,
+        },
+        1,
+    );
+    assert_eq!(order.totals(percent(8)), Err(MoneyError::Overflow));
+    assert_eq!(order.receipt_lines(), Err(MoneyError::Overflow));
+    // 明細の個数が表せなければ、最大の個数に丸めずにエラーにする
+    let order = Order::new(Currency::Usd).add(
+        coffee(Size::Medium)
+            .add_topping(&catalogue, "milk")
+            .unwrap()
+            .add_topping(&catalogue, "milk")
+            .unwrap(),
+        u32::MAX,
+    );
+    assert_eq!(order.receipt_lines(), Err(MoneyError::Overflow));
+
+    let order = Order::new(Currency::Jpy).add(coffee(Size::Medium), 1);
+    assert_eq!(
+        order.subtotal(),
+        Err(MoneyError::CurrencyMismatch(Currency::Jpy, Currency::Usd))
+    );
+}
@@ -0,0 +1,271 @@
+//! レシートの明細を構造化したデータとして扱い、様々な形式で出力するモジュール
+//!
+//! 明細は文字列ではなくReceiptLineの一覧として組み立て、
+//! 表示形式ごとのReceiptRendererで文字列に変換する
+use crate::money::{Money, MoneyError};
+use serde_json::json;
+
+/// レシートの1行分の明細
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptLine {
+    /// 商品名
+    pub name: String,
+    /// 数量
+    pub quantity: u32,
+    /// 1つ分の価格
+    pub unit_price: Money,
+    /// サイズなど、商品に付記する情報
+    pub modifiers: Vec<String>,
+}
+
+impl ReceiptLine {
+    /// 数量1つ分の明細を作る
+    pub fn new(name: &str, unit_price: Money) -> ReceiptLine {
+        ReceiptLine {
+            name: name.to_string(),
+            quantity: 1,
+            unit_price,
+            modifiers: Vec::new(),
+        }
+    }
+
+    /// 数量分の金額
+    pub fn amount(&self) -> Result<Money, MoneyError> {
+        self.unit_price.checked_mul(self.quantity)
+    }
+
+    /// 数量が2以上なら`milk topping ×2`のように数量を付けた商品名
+    pub fn label(&self) -> String {
+        if self.quantity > 1 {
+            format!("{} ×{}", self.name, self.quantity)
+        } else {
+            self.name.clone()
+        }
+    }
+}
+
+/// 明細の一覧と、小計や税などの集計行からなるレシート
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Receipt {
+    /// 明細
+    pub lines: Vec<ReceiptLine>,
+    /// 明細の後に表示する、見出しと金額の組
+    pub summary: Vec<(String, Money)>,
+}
+
+impl Receipt {
+    /// 明細だけのレシートを作る
+    pub fn new(lines: Vec<ReceiptLine>) -> Receipt {
+        Receipt {
+            lines,
+            summary: Vec::new(),
+        }
+    }
+
+    /// 集計行を追加したレシートを返す
+    pub fn with_summary(mut self, label: &str, amount: Money) -> Receipt {
+        self.summary.push((label.to_string(), amount));
+        self
+    }
+}
+
+/// レシートを文字列に変換する表示形式
+pub trait ReceiptRenderer {
+    /// receiptを表示用の文字列に変換する
+    /// 明細の金額を計算できなければエラーを返す
+    fn render(&self, receipt: &Receipt) -> Result<String, MoneyError>;
+}
+
+/// 画面に表示するための`商品名: 金額`形式の文字列
+pub struct PlainText;
+
+impl ReceiptRenderer for PlainText {
+    fn render(&self, receipt: &Receipt) -> Result<String, MoneyError> {
+        let mut rows = Vec::new();
+        for line in &receipt.lines {
+            rows.push(format!("{}: {}", line.label(), line.amount()?));
+            for modifier in &line.modifiers {
+                rows.push(format!("  {}", modifier));
+            }
+        }
+        if !receipt.summary.is_empty() {
+            rows.push("--------------------".to_string());
+        }
+        for (label, amount) in &receipt.summary {
+            rows.push(format!("{}: {}", label, amount));
+        }
+        Ok(rows.join("\n"))
+    }
+}
+
+/// 感熱紙プリンタ向けの、1行の文字数が決まった形式
+/// 商品名を左に、金額を右に揃え、収まらない商品名は切り詰める
+pub struct Thermal {
+    /// 1行の文字数
+    pub width: usize,
+}
+
+impl Thermal {
+    /// 58mm幅の感熱紙で一般的な1行の文字数
+    pub const DEFAULT_WIDTH: usize = 32;
+
+    /// 左右に振り分けた1行
+    /// rightが空なら左寄せだけにする
+    fn row(&self, left: &str, right: &str) -> String {
+        let right_width = right.chars().count();
+        let room = if right.is_empty() {
+            self.width
+        } else {
+            self.width.saturating_sub(right_width + 1)
+        };
+        let left: String = if left.chars().count() > room {
+            // 切り詰めたことが分かるように最後を`~`にする
+            let truncated: String = left.chars().take(room.saturating_sub(1)).collect();
+            format!("{}~", truncated.trim_end())
+        } else {
+            left.to_string()
+        };
+        if right.is_empty() {
+            return left;
+        }
+        let padding = self
+            .width
+            .saturating_sub(left.chars().count() + right_width);
+        format!("{}{}{}", left, " ".repeat(padding.max(1)), right)
+    }
+}
+
+impl Default for Thermal {
+    fn default() -> Thermal {
+        Thermal {
+            width: Thermal::DEFAULT_WIDTH,
+        }
+    }
+}
+
+impl ReceiptRenderer for Thermal {
+    fn render(&self, receipt: &Receipt) -> Result<String, MoneyError> {
+        let rule = "=".repeat(self.width);
+        let mut rows = vec![rule.clone()];
+        for line in &receipt.lines {
+            rows.push(self.row(&line.label(), &line.amount()?.to_string()));
+            for modifier in &line.modifiers {
+                rows.push(self.row(&format!("  + {}", modifier), ""));
+            }
+        }
+        rows.push("-".repeat(self.width));
+        for (label, amount) in &receipt.summary {
+            rows.push(self.row(&label.to_uppercase(), &amount.to_string()));
+        }
+        rows.push(rule);
+        Ok(rows.join("\n"))
+    }
+}
+
+/// 他のシステムに渡すためのJSON
+/// 金額は`"USD 3.30"`のような文字列で書き出す
+pub struct Json;
+
+impl ReceiptRenderer for Json {
+    fn render(&self, receipt: &Receipt) -> Result<String, MoneyError> {
+        let mut lines = Vec::new();
+        for line in &receipt.lines {
+            lines.push(json!({
+                "name": line.name,
+                "quantity": line.quantity,
+                "unit_price": line.unit_price,
+                "amount": line.amount()?,
+                "modifiers": line.modifiers,
+            }));
+        }
+        let summary: Vec<_> = receipt
+            .summary
+            .iter()
+            .map(|(label, amount)| json!({ "label": label, "amount": amount }))
+            .collect();
+        let receipt = json!({ "lines": lines, "summary": summary });
+        // Valueの書き出しは失敗しない
+        Ok(serde_json::to_string_pretty(&receipt).unwrap_or_default())
+    }
+}
+
+/// Webページに埋め込むためのHTMLの表
+pub struct Html;
+
+impl ReceiptRenderer for Html {
+    fn render(&self, receipt: &Receipt) -> Result<String, MoneyError> {
+        let mut html = String::from("<table class=\"receipt\">\n<tbody>\n");
+        for line in &receipt.lines {
+            let mut name = escape_html(&line.label());
+            for modifier in &line.modifiers {
+                name.push_str(&format!("<br><small>{}</small>", escape_html(modifier)));
+            }
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td></tr>\n",
+                name,
+                escape_html(&line.amount()?.to_string())
+            ));
+        }
+        html.push_str("</tbody>\n<tfoot>\n");
+        for (label, amount) in &receipt.summary {
+            html.push_str(&format!(
+                "<tr><th>{}</th><td>{}</td></tr>\n",
+                escape_html(label),
+                escape_html(&amount.to_string())
+            ));
+        }
+        html.push_str("</tfoot>\n</table>");
+        Ok(html)
+    }
+}
+
+/// HTMLで特別な意味を持つ文字を実体参照に置き換える
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn test_renderers() {
+    use crate::money::Currency;
+
+    let mut milk = ReceiptLine::new("milk topping", Money::new(330, Currency::Usd));
+    milk.quantity = 2;
+    let mut coffee = ReceiptLine::new("coffee <drink>", Money::new(1000, Currency::Usd));
+    coffee.modifiers.push("extra hot".to_string());
+    let receipt =
+        Receipt::new(vec![coffee, milk]).with_summary("total", Money::new(1660, Currency::Usd));
+
+    assert_eq!(
+        PlainText.render(&receipt).unwrap(),
+        "coffee <drink>: $10.00\n  extra hot\nmilk topping ×2: $6.60\n--------------------\ntotal: $16.60"
+    );
+    let thermal = Thermal { width: 22 }.render(&receipt).unwrap();
+    let rows: Vec<_> = thermal.lines().collect();
+    assert_eq!(rows[1], "coffee <drink>  $10.00");
+    assert_eq!(rows[2], "  + extra hot");
+    assert_eq!(rows[3], "milk topping ×2  $6.60");
+    assert_eq!(rows[5], "TOTAL           $16.60");
+    assert_eq!(
+        Thermal { width: 12 }.row("milk topping", "$6.60"),
+        "milk~  $6.60"
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&Json.render(&receipt).unwrap()).unwrap();
+    assert_eq!(json["lines"][1]["amount"], "USD 6.60");
+    assert_eq!(json["lines"][0]["modifiers"][0], "extra hot");
+
+    let html = Html.render(&receipt).unwrap();
+    assert!(html.contains("<td>coffee &lt;drink&gt;<br><small>extra hot</small></td>"));
+    assert!(html.contains("<tr><th>total</th><td>$16.60</td></tr>"));
+}
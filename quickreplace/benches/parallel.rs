@@ -0,0 +1,79 @@
+//! 合成したディレクトリツリーに対して`--in-place`の置換にかかる時間を`--jobs`ごとに測る
+//! `cargo bench`で実行する
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Instant;
+
+/// 作るディレクトリの数
+const DIRECTORIES: usize = 20;
+/// 1つのディレクトリに作るファイルの数
+const FILES_PER_DIRECTORY: usize = 50;
+/// 1つのファイルの行数
+const LINES_PER_FILE: usize = 500;
+
+/// rootの下にDIRECTORIES x FILES_PER_DIRECTORY個のテキストファイルを作る
+fn create_tree(root: &Path) {
+    let line = "let user_id = fetch_user(user_id).unwrap_or_default(); // user_id\n";
+    let content = line.repeat(LINES_PER_FILE);
+    for d in 0..DIRECTORIES {
+        let dir = root.join(format!("dir{:03}", d));
+        fs::create_dir_all(&dir).unwrap();
+        for f in 0..FILES_PER_DIRECTORY {
+            fs::write(dir.join(format!("file{:03}.rs", f)), &content).unwrap();
+        }
+    }
+}
+
+/// jobs個のスレッドでツリー全体を置換し、かかった秒数を返す
+/// 置換と逆置換を続けて行い、ツリーを元の内容に戻す
+/// ジャーナルの保存は1スレッドで行われ、`--jobs`の比較を歪めるので記録しない
+fn run(root: &Path, jobs: usize) -> f64 {
+    let start = Instant::now();
+    for (target, replacement) in [(r"user_(\w+)", "account_$1"), (r"account_(\w+)", "user_$1")] {
+        let status = Command::new(env!("CARGO_BIN_EXE_quickreplace"))
+            .args([
+                "--in-place",
+                "--no-journal",
+                "--jobs",
+                &jobs.to_string(),
+                target,
+                replacement,
+            ])
+            .arg(root)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .expect("failed to run quickreplace");
+        assert!(status.success());
+    }
+    start.elapsed().as_secs_f64()
+}
+
+fn main() {
+    let root = tempfile::tempdir().unwrap();
+    create_tree(root.path());
+    println!(
+        "{} files x {} lines",
+        DIRECTORIES * FILES_PER_DIRECTORY,
+        LINES_PER_FILE
+    );
+
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut jobs = vec![1, 2, 4, cpus];
+    jobs.sort();
+    jobs.dedup();
+    let baseline = run(root.path(), 1);
+    for jobs in jobs {
+        let seconds = if jobs == 1 {
+            baseline
+        } else {
+            run(root.path(), jobs)
+        };
+        println!(
+            "--jobs {:>3}: {:>8.3}s  ({:.2}x)",
+            jobs,
+            seconds,
+            baseline / seconds
+        );
+    }
+}
@@ -0,0 +1,292 @@
+//! コマンドライン引数の定義
+//! ヘルプ、シェル補完、manページは全てこの定義から生成する
+use clap::{CommandFactory, Parser};
+use clap_complete::Shell;
+use quickreplace::syntax::{Language, Scope};
+use quickreplace::{diff, error, watch, write};
+use std::io;
+use std::num::NonZeroUsize;
+
+/// quickreplaceが受け付ける引数
+#[derive(Debug, Parser)]
+#[command(
+    name = "quickreplace",
+    version,
+    about = "change occurences of one string into another",
+    override_usage = "quickreplace [OPTIONS] <target> <replacement> <INPUT> <OUTPUT>
+       quickreplace --in-place [OPTIONS] <target> <replacement> <PATH>...
+       quickreplace --rules <FILE> [OPTIONS] <INPUT> <OUTPUT>
+       quickreplace --rules <FILE> --in-place [OPTIONS] <PATH>...
+       quickreplace --paths [OPTIONS] <target> <replacement> <PATH>...
+       quickreplace --in-place --watch [OPTIONS] <target> <replacement> <PATH>...
+       quickreplace --search [OPTIONS] <target> <PATH>...
+       quickreplace --undo [RUN_ID]",
+    after_help = format!(
+        "INPUT and OUTPUT may be '-' to read from stdin or write to stdout.\n\
+         Replaced and found matches are reported as line:column of the original file, \
+         with columns counted in bytes.\n\
+         Exits with {} if anything was replaced or found, {} if nothing matched and {} on errors.",
+        error::EXIT_CHANGED,
+        error::EXIT_NO_MATCH,
+        error::EXIT_ERROR
+    )
+)]
+pub struct Cli {
+    #[arg(
+        value_name = "ARGS",
+        help = "target, replacement and the files to work on, depending on the mode"
+    )]
+    pub args: Vec<String>,
+
+    #[arg(long, help = "rewrite every file under PATH... in place")]
+    pub in_place: bool,
+    #[arg(
+        long = "paths",
+        conflicts_with_all = ["in_place", "search", "interactive", "stream"],
+        help = "rename files and directories under PATH... instead of editing their contents \
+                (renames are not recorded for undo)"
+    )]
+    pub rename_paths: bool,
+    #[arg(
+        short = 'f',
+        long = "rules",
+        value_name = "FILE",
+        help = "apply every s/target/replacement/flags rule in FILE"
+    )]
+    pub rules_file: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["in_place", "dry_run", "interactive", "stream"],
+        help = "list matches in PATH... instead of replacing them"
+    )]
+    pub search: bool,
+    #[arg(
+        short,
+        long,
+        requires = "search",
+        help = "with --search, print the number of matches per file"
+    )]
+    pub count: bool,
+    #[arg(
+        short = 'l',
+        long,
+        requires = "search",
+        conflicts_with = "count",
+        help = "with --search, print only the names of matching files"
+    )]
+    pub files_with_matches: bool,
+    #[arg(
+        long,
+        requires = "search",
+        help = "with --search, print results as JSON lines"
+    )]
+    pub json: bool,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "only process files matching GLOB (repeatable)"
+    )]
+    pub include: Vec<String>,
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "skip files and directories matching GLOB (repeatable)"
+    )]
+    pub exclude: Vec<String>,
+    #[arg(long, help = "do not respect .gitignore and .ignore files")]
+    pub no_ignore: bool,
+
+    #[arg(
+        short = 'F',
+        long,
+        help = "treat target as literal strings, one per line"
+    )]
+    pub fixed_strings: bool,
+    #[arg(long, help = "match case-insensitively")]
+    pub ignore_case: bool,
+    #[arg(short, long, help = "only match whole words")]
+    pub word: bool,
+    #[arg(long, help = "let ^ and $ match at line boundaries")]
+    pub multi_line: bool,
+    #[arg(long, help = "let . match newlines")]
+    pub dot_all: bool,
+    #[arg(
+        long,
+        value_name = "SCOPE",
+        help = "only match in code, comments or string literals of Rust, C-family and Python \
+                sources; files in other languages are left alone"
+    )]
+    pub only: Option<Scope>,
+    #[arg(
+        long,
+        value_name = "LANG",
+        help = "tokenize files as LANG for --only instead of guessing from the extension"
+    )]
+    pub language: Option<Language>,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "in each file, only replace the Nth match of each rule (with --max-replacements, \
+                start from the Nth match)"
+    )]
+    pub nth: Option<NonZeroUsize>,
+    #[arg(
+        long,
+        value_name = "N",
+        help = "in each file, replace at most N matches of each rule"
+    )]
+    pub max_replacements: Option<NonZeroUsize>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "read and write files as NAME, e.g. shift_jis (default auto)"
+    )]
+    pub encoding: Option<String>,
+    #[arg(
+        long,
+        help = "make sure the replacement only refers to existing groups"
+    )]
+    pub template_check: bool,
+
+    #[arg(
+        long,
+        visible_alias = "diff",
+        conflicts_with = "interactive",
+        help = "show a unified diff instead of writing any file"
+    )]
+    pub dry_run: bool,
+    #[arg(short, long, help = "confirm each match before replacing it")]
+    pub interactive: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["dry_run", "interactive", "nth", "max_replacements"],
+        help = "replace line by line with bounded memory (runs are not recorded for undo)"
+    )]
+    pub stream: bool,
+    #[arg(
+        long,
+        requires = "in_place",
+        conflicts_with_all = ["dry_run", "interactive"],
+        help = "keep running and replace again whenever files under PATH... change \
+                (runs are not recorded for undo)"
+    )]
+    pub watch: bool,
+    #[arg(
+        long,
+        value_name = "MS",
+        requires = "watch",
+        default_value_t = watch::DEFAULT_DEBOUNCE_MS,
+        help = "with --watch, wait until no file has changed for MS milliseconds"
+    )]
+    pub debounce: u64,
+    #[arg(
+        short,
+        long,
+        value_name = "N",
+        help = "process N files at once (default: number of CPUs)"
+    )]
+    pub jobs: Option<NonZeroUsize>,
+
+    #[arg(
+        long,
+        help = format!(
+            "keep the original file with a '{}' suffix",
+            write::DEFAULT_BACKUP_SUFFIX
+        )
+    )]
+    pub backup: bool,
+    #[arg(long, value_name = "S", help = "keep the original file with suffix S")]
+    pub backup_suffix: Option<String>,
+    #[arg(
+        long,
+        help = "do not record the run for --undo in .quickreplace/ \
+                (the nearest existing one, or the one at the git repository root)"
+    )]
+    pub no_journal: bool,
+    #[arg(long, help = "keep the modification time of rewritten files")]
+    pub preserve_mtime: bool,
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = diff::DEFAULT_CONTEXT,
+        help = "lines of context around each change"
+    )]
+    pub context: usize,
+
+    #[arg(
+        long,
+        value_name = "SHELL",
+        exclusive = true,
+        help = "print a completion script for SHELL and exit"
+    )]
+    pub completions: Option<Shell>,
+    #[arg(long, exclusive = true, help = "print a man page and exit")]
+    pub man: bool,
+    /// `--in-place`で書き換えたファイルのうち、その後変更されていないものを元に戻す
+    /// 置換のtargetと区別できるように、サブコマンドではなくオプションにする
+    #[arg(
+        long,
+        value_name = "RUN_ID",
+        num_args = 0..=1,
+        exclusive = true,
+        help = "restore the files rewritten by an --in-place run (default: the latest run)"
+    )]
+    pub undo: Option<Option<String>>,
+}
+
+/// 補完スクリプトをshell向けに標準出力へ書き出す
+pub fn print_completions(shell: Shell) {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, name, &mut io::stdout());
+}
+
+/// roff形式のmanページを標準出力へ書き出す
+pub fn print_man_page() -> io::Result<()> {
+    clap_mangen::Man::new(Cli::command()).render(&mut io::stdout())
+}
+
+#[test]
+fn test_cli() {
+    Cli::command().debug_assert();
+
+    let cli = Cli::try_parse_from(["quickreplace", "-F", "-j", "2", "a", "b", "-", "out"]).unwrap();
+    assert!(cli.fixed_strings);
+    assert_eq!(cli.jobs, NonZeroUsize::new(2));
+    assert_eq!(cli.args, ["a", "b", "-", "out"]);
+
+    assert!(Cli::try_parse_from(["quickreplace", "--count", "a", "."]).is_err());
+    assert!(Cli::try_parse_from(["quickreplace", "--dry-run", "-i", "a", "b", "x", "y"]).is_err());
+    assert!(Cli::try_parse_from(["quickreplace", "-j", "0", "a", "b", "x", "y"]).is_err());
+    assert!(Cli::try_parse_from(["quickreplace", "--nth", "0", "a", "b", "x", "y"]).is_err());
+    assert!(Cli::try_parse_from([
+        "quickreplace",
+        "--stream",
+        "--max-replacements",
+        "1",
+        "a",
+        "b",
+        "x",
+        "y"
+    ])
+    .is_err());
+
+    assert!(Cli::try_parse_from(["quickreplace", "--watch", "a", "b", "x", "y"]).is_err());
+    let cli =
+        Cli::try_parse_from(["quickreplace", "--in-place", "--watch", "a", "b", "."]).unwrap();
+    assert_eq!(cli.debounce, watch::DEFAULT_DEBOUNCE_MS);
+
+    assert!(Cli::try_parse_from(["quickreplace", "--paths", "--in-place", "a", "b", "."]).is_err());
+
+    let cli = Cli::try_parse_from(["quickreplace", "--undo", "1700000000-1"]).unwrap();
+    assert_eq!(cli.undo, Some(Some("1700000000-1".to_string())));
+    let cli = Cli::try_parse_from(["quickreplace", "--undo"]).unwrap();
+    assert_eq!(cli.undo, Some(None));
+    assert!(Cli::try_parse_from(["quickreplace", "--undo", "--in-place", "a", "b", "."]).is_err());
+    // `undo`という名前のtargetも、他の文字列と同じく置換の引数になる
+    let cli = Cli::try_parse_from(["quickreplace", "undo", "redo", "x", "y"]).unwrap();
+    assert_eq!(cli.undo, None);
+    assert_eq!(cli.args, ["undo", "redo", "x", "y"]);
+}
@@ -0,0 +1,50 @@
+//! 置換前後のテキストをunified diff形式で表示するモジュール
+use similar::TextDiff;
+use text_colorizer::*;
+
+/// 変更行の前後に表示するコンテキスト行数の既定値
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// oldからnewへの変更をunified diff形式の文字列で返す
+/// 変更が無ければ空文字列を返す
+pub fn unified_diff(
+    old_label: &str,
+    new_label: &str,
+    old: &str,
+    new: &str,
+    context: usize,
+) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(context)
+        .header(old_label, new_label)
+        .to_string()
+}
+
+/// unified diffを行の種類ごとに色分けして標準出力に表示する
+pub fn print_colored(diff: &str) {
+    for line in diff.lines() {
+        if line.starts_with("---") || line.starts_with("+++") {
+            println!("{}", line.bold());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+#[test]
+fn test_unified_diff() {
+    let old = "a\nb\nc\nd\ne\n";
+    let new = "a\nb\nC\nd\ne\n";
+    assert_eq!(
+        unified_diff("a/x", "b/x", old, new, 1),
+        "--- a/x\n+++ b/x\n@@ -2,3 +2,3 @@\n b\n-c\n+C\n d\n"
+    );
+    assert_eq!(unified_diff("a/x", "b/x", old, old, 3), "");
+}
@@ -0,0 +1,337 @@
+//! UTF-8以外の文字コードで書かれたファイルを扱うモジュール
+//! 読み込み時に文字コードを判定してUTF-8に変換し、書き戻す時は元の文字コードと改行コードに戻す
+use crate::rules::TextEdit;
+use encoding_rs::{Encoding, EUC_JP, SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8};
+
+/// 読み込んだファイルの内容
+#[derive(Debug, PartialEq)]
+pub enum Decoded {
+    /// 文字コードが判明し、テキストとして扱える内容
+    Text {
+        /// UTF-8に変換したテキスト
+        text: String,
+        /// 元の文字コード
+        encoding: &'static Encoding,
+        /// 元のファイルにBOMが付いていたかどうか
+        bom: bool,
+    },
+    /// 文字コードが判明しなかったので、バイト列のまま扱う内容
+    Bytes(Vec<u8>),
+}
+
+/// `--encoding`に指定された名前を文字コードに変換する
+/// `auto`なら自動判定を表すNoneを返す
+pub fn parse_label(label: &str) -> Result<Option<&'static Encoding>, String> {
+    if label.eq_ignore_ascii_case("auto") {
+        return Ok(None);
+    }
+    match Encoding::for_label(label.as_bytes()) {
+        Some(v) => Ok(Some(v)),
+        None => Err(format!("unknown encoding '{}'", label)),
+    }
+}
+
+/// dataをUTF-16のテキストとして扱うかどうかを返す
+/// UTF-16のテキストはNULバイトを含むので、バイナリ判定より先に確認する必要がある
+pub fn is_utf16(data: &[u8], forced: Option<&'static Encoding>) -> bool {
+    match forced {
+        Some(encoding) => encoding == UTF_16LE || encoding == UTF_16BE,
+        None => matches!(Encoding::for_bom(data), Some((e, _)) if e != UTF_8),
+    }
+}
+
+/// dataの文字コードを判定してテキストに変換する
+/// forcedが指定されていればその文字コードとして変換し、変換できなければエラーにする
+/// 自動判定ではBOM、UTF-8、Shift_JISとEUC-JPの順に試し、いずれでもなければバイト列のまま返す
+/// Latin-1などのバイト列もShift_JISとして変換できてしまうことがあるので、
+/// 日本語の文章らしさの点数が正でなければShift_JISやEUC-JPとはみなさない
+/// 書き戻すと元と違うバイト列になってしまう内容は、置換していない箇所まで変わってしまうので
+/// テキストとして扱わない
+pub fn decode(data: &[u8], forced: Option<&'static Encoding>) -> Result<Decoded, String> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(data) {
+        if forced.is_none() || forced == Some(encoding) {
+            let text = decode_exact(encoding, &data[bom_length..])?;
+            return Ok(Decoded::Text {
+                text,
+                encoding,
+                bom: true,
+            });
+        }
+    }
+    if let Some(encoding) = forced {
+        let text = decode_exact(encoding, data)?;
+        return Ok(Decoded::Text {
+            text,
+            encoding,
+            bom: false,
+        });
+    }
+
+    if let Ok(text) = std::str::from_utf8(data) {
+        return Ok(Decoded::Text {
+            text: text.to_string(),
+            encoding: UTF_8,
+            bom: false,
+        });
+    }
+    // 同点の場合は後の候補が選ばれるので、より一般的なShift_JISを後に置く
+    let candidates = [EUC_JP, SHIFT_JIS]
+        .into_iter()
+        .filter_map(|encoding| Some((encoding, decode_exact(encoding, data).ok()?)));
+    let best = candidates
+        .map(|(encoding, text)| (japanese_score(&text), encoding, text))
+        .max_by_key(|(score, _, _)| *score);
+    match best {
+        Some((score, encoding, text)) if score > 0 => Ok(Decoded::Text {
+            text,
+            encoding,
+            bom: false,
+        }),
+        _ => Ok(Decoded::Bytes(data.to_vec())),
+    }
+}
+
+/// dataを置き換え文字を使わずにencodingとして変換する
+/// 変換できないバイト列を含む場合はNoneを返す
+fn decode_as(encoding: &'static Encoding, data: &[u8]) -> Option<String> {
+    encoding
+        .decode_without_bom_handling_and_without_replacement(data)
+        .map(|text| text.into_owned())
+}
+
+/// dataをencodingとして変換し、変換したテキストを元の文字コードに戻すと
+/// dataと同じバイト列になることを確かめる
+/// Shift_JISのNEC選定IBM拡張文字(0xED、0xEEの行)のように、
+/// 変換はできても書き戻すと別のバイト列になる文字があればエラーにする
+fn decode_exact(encoding: &'static Encoding, data: &[u8]) -> Result<String, String> {
+    let text =
+        decode_as(encoding, data).ok_or_else(|| format!("invalid {} text", encoding.name()))?;
+    if encoding != UTF_8 && encode(&text, encoding, false).ok().as_deref() != Some(data) {
+        return Err(format!(
+            "contains {} characters that cannot be written back unchanged",
+            encoding.name()
+        ));
+    }
+    Ok(text)
+}
+
+/// 日本語の文章らしさを表す点数
+/// Shift_JISとEUC-JPはどちらとしても変換できてしまうバイト列があるので、
+/// 変換結果にひらがな・カタカナ・漢字が多いほど正しい文字コードとみなす
+/// 誤った文字コードで変換すると半角カタカナや私用領域の文字が出やすいので減点する
+fn japanese_score(text: &str) -> i64 {
+    text.chars()
+        .map(|c| match c {
+            '\u{3000}'..='\u{30ff}' | '\u{4e00}'..='\u{9fff}' | '\u{ff01}'..='\u{ff5e}' => 2,
+            '\u{ff61}'..='\u{ff9f}' | '\u{e000}'..='\u{f8ff}' => -3,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// textをencodingへ変換する
+/// encodingで表せない文字を含む場合はエラーにする
+pub fn encode(text: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>, String> {
+    let mut data = Vec::with_capacity(text.len());
+    // encoding_rsはUTF-16への変換を提供しないので自前で変換する
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let little_endian = encoding == UTF_16LE;
+        let units = bom.then_some('\u{feff}' as u16).into_iter();
+        for unit in units.chain(text.encode_utf16()) {
+            if little_endian {
+                data.extend_from_slice(&unit.to_le_bytes());
+            } else {
+                data.extend_from_slice(&unit.to_be_bytes());
+            }
+        }
+        return Ok(data);
+    }
+    if bom && encoding == UTF_8 {
+        data.extend_from_slice(b"\xef\xbb\xbf");
+    }
+    let (encoded, _, had_errors) = encoding.encode(text);
+    if had_errors {
+        return Err(format!(
+            "the replaced text cannot be represented in {}",
+            encoding.name()
+        ));
+    }
+    data.extend_from_slice(&encoded);
+    Ok(data)
+}
+
+/// 元のテキストの改行コードがCRLFなら、置換によって入ったLFをCRLFに揃える
+/// editsは置換で書き換えた範囲で、その外側の改行は元のテキストのまま変えない
+/// 揃えた後のテキストと、それに合わせてずらしたeditsを返す
+pub fn restore_line_endings(
+    original: &str,
+    replaced: String,
+    edits: &[TextEdit],
+) -> (String, Vec<TextEdit>) {
+    let crlf = original.matches("\r\n").count();
+    let lf = original.matches('\n').count() - crlf;
+    if crlf == 0 || crlf < lf {
+        return (replaced, edits.to_vec());
+    }
+    let mut result = String::with_capacity(replaced.len());
+    let mut restored = Vec::with_capacity(edits.len());
+    let mut last = 0;
+    for edit in edits {
+        result.push_str(&replaced[last..edit.replaced.start]);
+        let start = result.len();
+        let mut previous = replaced[..edit.replaced.start].chars().next_back();
+        for c in replaced[edit.replaced.clone()].chars() {
+            if c == '\n' && previous != Some('\r') {
+                result.push('\r');
+            }
+            result.push(c);
+            previous = Some(c);
+        }
+        restored.push(TextEdit {
+            original: edit.original.clone(),
+            replaced: start..result.len(),
+        });
+        last = edit.replaced.end;
+    }
+    result.push_str(&replaced[last..]);
+    (result, restored)
+}
+
+/// 元のテキストoriginalと置換後のテキストreplacedで対応するeditsを、
+/// それぞれをencodingへ変換したバイト列での範囲に変換する
+/// 範囲の外側を区切って変換した長さを足し合わせるので、ISO-2022-JPのように
+/// 状態を持つ文字コードでは正しくないことがあり、呼び出し側で確かめる必要がある
+/// encodingで表せない文字があればNoneを返す
+pub fn byte_edits(
+    original: &str,
+    replaced: &str,
+    edits: &[TextEdit],
+    encoding: &'static Encoding,
+    bom: bool,
+) -> Option<Vec<TextEdit>> {
+    let original_points: Vec<usize> = edits
+        .iter()
+        .flat_map(|edit| [edit.original.start, edit.original.end])
+        .collect();
+    let replaced_points: Vec<usize> = edits
+        .iter()
+        .flat_map(|edit| [edit.replaced.start, edit.replaced.end])
+        .collect();
+    let original_offsets = byte_offsets(original, &original_points, encoding, bom)?;
+    let replaced_offsets = byte_offsets(replaced, &replaced_points, encoding, bom)?;
+    Some(
+        original_offsets
+            .chunks(2)
+            .zip(replaced_offsets.chunks(2))
+            .map(|(o, r)| TextEdit {
+                original: o[0]..o[1],
+                replaced: r[0]..r[1],
+            })
+            .collect(),
+    )
+}
+
+/// textの昇順に並んだ位置pointsが、encodingへ変換したバイト列で何バイト目になるかを返す
+fn byte_offsets(
+    text: &str,
+    points: &[usize],
+    encoding: &'static Encoding,
+    bom: bool,
+) -> Option<Vec<usize>> {
+    let mut offset = encode("", encoding, bom).ok()?.len();
+    let mut last = 0;
+    let mut offsets = Vec::with_capacity(points.len());
+    for &point in points {
+        offset += encode(text.get(last..point)?, encoding, false).ok()?.len();
+        offsets.push(offset);
+        last = point;
+    }
+    Some(offsets)
+}
+
+#[test]
+fn test_decode() {
+    let (sjis, _, _) = SHIFT_JIS.encode("置換する文字列");
+    let (euc, _, _) = EUC_JP.encode("置換する文字列");
+    for (data, encoding) in [(sjis.to_vec(), SHIFT_JIS), (euc.to_vec(), EUC_JP)] {
+        assert_eq!(
+            decode(&data, None),
+            Ok(Decoded::Text {
+                text: "置換する文字列".to_string(),
+                encoding,
+                bom: false,
+            })
+        );
+    }
+
+    let utf16 = b"\xff\xfea\x00b\x00".to_vec();
+    assert!(is_utf16(&utf16, None));
+    assert_eq!(
+        decode(&utf16, None),
+        Ok(Decoded::Text {
+            text: "ab".to_string(),
+            encoding: UTF_16LE,
+            bom: true,
+        })
+    );
+    assert_eq!(
+        decode(b"\x80\xff", None),
+        Ok(Decoded::Bytes(vec![0x80, 0xff]))
+    );
+    assert!(decode(b"\x80\xff", Some(SHIFT_JIS)).is_err());
+    // NEC選定IBM拡張文字は変換できるが、書き戻すと0xFA、0xFBの行のバイト列になってしまう
+    let ibm = b"\xed\x40 foo".to_vec();
+    assert_eq!(decode(&ibm, None), Ok(Decoded::Bytes(ibm.clone())));
+    assert!(decode(&ibm, Some(SHIFT_JIS)).is_err());
+    // Latin-1の\xdcと\xc7はShift_JISの半角カタカナとしても変換できるが、日本語らしくない
+    assert_eq!(
+        decode(b"\xdcber \xc7a", None),
+        Ok(Decoded::Bytes(b"\xdcber \xc7a".to_vec()))
+    );
+}
+
+#[test]
+fn test_encode() {
+    let (sjis, _, _) = SHIFT_JIS.encode("文字");
+    assert_eq!(encode("文字", SHIFT_JIS, false).unwrap(), sjis.to_vec());
+    // 全角文字はShift_JISで2バイトになる
+    let edits = [TextEdit {
+        original: 3..6,
+        replaced: 3..4,
+    }];
+    assert_eq!(
+        byte_edits("文字列", "文x列", &edits, SHIFT_JIS, false),
+        Some(vec![TextEdit {
+            original: 2..4,
+            replaced: 2..3,
+        }])
+    );
+    assert_eq!(encode("ab", UTF_16BE, true).unwrap(), b"\xfe\xff\x00a\x00b");
+    assert_eq!(encode("a", UTF_8, true).unwrap(), b"\xef\xbb\xbfa");
+    assert!(encode("😀", SHIFT_JIS, false).is_err());
+}
+
+#[test]
+fn test_restore_line_endings() {
+    let edit = |original, replaced| TextEdit { original, replaced };
+    assert_eq!(
+        restore_line_endings(
+            "a\r\nb\r\n",
+            "a\nx\r\nb\r\n".to_string(),
+            &[edit(1..1, 1..3)]
+        ),
+        ("a\r\nx\r\nb\r\n".to_string(), vec![edit(1..1, 1..4)])
+    );
+    assert_eq!(
+        restore_line_endings("a\nb\n", "a\nx\n".to_string(), &[edit(2..3, 2..4)]).0,
+        "a\nx\n"
+    );
+    // 置換していない行の改行は、少数派のLFでもそのまま残す
+    let (text, edits) = restore_line_endings(
+        "a\r\nb\r\nc\n",
+        "A\n\r\nb\r\nc\n".to_string(),
+        &[edit(0..1, 0..2)],
+    );
+    assert_eq!(text, "A\r\n\r\nb\r\nc\n");
+    assert_eq!(edits, vec![edit(0..1, 0..3)]);
+}
@@ -0,0 +1,167 @@
+//! quickreplaceで起こるエラーと、スクリプトから判別できる終了コード
+//!
+//! 終了コードは以下の通り
+//! - `0`: 置換した(検索モードではマッチした)か、`--completions`や`--man`が出力を終えた
+//! - `1`: 何もマッチしなかった
+//! - `2`: 引数の誤りや読み書きの失敗などのエラーが起きた
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// 置換した場合の終了コード
+pub const EXIT_CHANGED: i32 = 0;
+/// `--completions`や`--man`のように、置換せずに正常に終えた場合の終了コード
+pub const EXIT_SUCCESS: i32 = 0;
+/// 何もマッチしなかった場合の終了コード
+pub const EXIT_NO_MATCH: i32 = 1;
+/// エラーが起きた場合の終了コード
+pub const EXIT_ERROR: i32 = 2;
+
+/// quickreplaceで起こるエラー
+#[derive(Debug)]
+pub enum QuickReplaceError {
+    /// ファイルや標準入力を読み込めなかった
+    Read { path: String, source: io::Error },
+    /// ファイルや標準出力へ書き込めなかった
+    Write { path: String, source: io::Error },
+    /// PATHに指定されたディレクトリを辿れなかった
+    Walk(ignore::Error),
+    /// `--watch`でPATHの変更を監視できなかった
+    Watch(notify::Error),
+    /// targetを正規表現としてコンパイルできなかった
+    Regex {
+        pattern: String,
+        source: regex::Error,
+    },
+    /// replacementのテンプレートが正しくない
+    Template { template: String, message: String },
+    /// `s/target/replacement/flags`形式のルールの書き方が正しくない
+    Syntax(String),
+    /// ルールファイルのある行のルールが正しくない
+    RulesFile {
+        path: String,
+        line: usize,
+        source: Box<QuickReplaceError>,
+    },
+    /// ルールファイルにルールが1つも無い
+    NoRules(String),
+    /// 1行ずつ区切ると全体を置換した場合と結果が変わるルールを、1行ずつ適用しようとした
+    NotStreamable(String),
+    /// 文字コードの変換に失敗した
+    Encoding { path: String, message: String },
+    /// テキストファイルでないので置換できない
+    NotText(String),
+    /// 複数のファイルを処理するうちのいくつかが失敗した
+    /// 個々のエラーは処理中に報告済み
+    Failed(usize),
+    /// 元に戻すための記録を読み込めなかった
+    Journal {
+        path: String,
+        source: serde_json::Error,
+    },
+    /// 元に戻す実行が見つからなかった
+    /// IDが指定されていなければ、記録が1つも無い
+    NoJournal(Option<String>),
+    /// 元に戻す実行の後に変更されたために、元に戻せなかったファイルがある
+    UndoConflicts(usize),
+    /// `--paths`でファイルかディレクトリの名前を変更できなかった
+    Rename {
+        from: String,
+        to: String,
+        source: io::Error,
+    },
+    /// `--paths`で変更後のパスが重なるか既存のものを上書きするので、何も変更しなかった
+    /// 個々の問題は報告済み
+    RenameConflicts(usize),
+    /// 何もマッチしなかった
+    NoMatch,
+}
+
+impl QuickReplaceError {
+    /// このエラーで終了する場合の終了コード
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            QuickReplaceError::NoMatch => EXIT_NO_MATCH,
+            _ => EXIT_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for QuickReplaceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuickReplaceError::Read { path, .. } => write!(f, "failed to read '{}'", path),
+            QuickReplaceError::Write { path, .. } => write!(f, "failed to write '{}'", path),
+            QuickReplaceError::Walk(_) => write!(f, "failed to walk paths"),
+            QuickReplaceError::Watch(_) => write!(f, "failed to watch paths"),
+            QuickReplaceError::Regex { pattern, .. } => {
+                write!(f, "invalid regular expression '{}'", pattern)
+            }
+            QuickReplaceError::Template { template, message } => {
+                write!(f, "invalid replacement '{}': {}", template, message)
+            }
+            QuickReplaceError::Syntax(message) => write!(f, "invalid rule: {}", message),
+            QuickReplaceError::RulesFile { path, line, .. } => {
+                write!(f, "{}:{}: failed to load rule", path, line)
+            }
+            QuickReplaceError::NoRules(path) => write!(f, "{}: no rules found", path),
+            QuickReplaceError::Encoding { path, message } => write!(f, "'{}': {}", path, message),
+            QuickReplaceError::NotStreamable(rule) => write!(
+                f,
+                "'{}' cannot be applied line by line; it may match across lines, \
+                 anchor to the whole input (use (?m) for line anchors) \
+                 or be limited to code, comments or strings",
+                rule
+            ),
+            QuickReplaceError::NotText(path) => write!(f, "'{}' is not a text file", path),
+            QuickReplaceError::Failed(count) => write!(f, "failed to process {} files", count),
+            QuickReplaceError::Journal { path, .. } => write!(f, "invalid journal '{}'", path),
+            QuickReplaceError::NoJournal(Some(id)) => write!(f, "no run '{}' to undo", id),
+            QuickReplaceError::NoJournal(None) => write!(f, "no run to undo"),
+            QuickReplaceError::UndoConflicts(count) => write!(
+                f,
+                "{} files were modified after the run and were not restored",
+                count
+            ),
+            QuickReplaceError::Rename { from, to, .. } => {
+                write!(f, "failed to rename '{}' to '{}'", from, to)
+            }
+            QuickReplaceError::RenameConflicts(count) => {
+                write!(f, "{} renames conflict; nothing was renamed", count)
+            }
+            QuickReplaceError::NoMatch => write!(f, "no matches found"),
+        }
+    }
+}
+
+impl Error for QuickReplaceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            QuickReplaceError::Read { source, .. }
+            | QuickReplaceError::Write { source, .. }
+            | QuickReplaceError::Rename { source, .. } => Some(source),
+            QuickReplaceError::Walk(source) => Some(source),
+            QuickReplaceError::Watch(source) => Some(source),
+            QuickReplaceError::Regex { source, .. } => Some(source),
+            QuickReplaceError::RulesFile { source, .. } => Some(source.as_ref()),
+            QuickReplaceError::Journal { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_error_chain() {
+    let error = QuickReplaceError::RulesFile {
+        path: "rules.sed".to_string(),
+        line: 3,
+        source: Box::new(crate::rules::Rule::new("(", "", Default::default()).unwrap_err()),
+    };
+    assert_eq!(error.to_string(), "rules.sed:3: failed to load rule");
+    assert_eq!(error.exit_code(), EXIT_ERROR);
+    let regex = error.source().unwrap();
+    assert_eq!(regex.to_string(), "invalid regular expression '('");
+    // regexクレートのエラーは位置を示す`^`を含む
+    assert!(regex.source().unwrap().to_string().contains('^'));
+    assert_eq!(QuickReplaceError::NoMatch.exit_code(), EXIT_NO_MATCH);
+}
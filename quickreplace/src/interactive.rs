@@ -0,0 +1,197 @@
+//! マッチ箇所ごとに置換するかどうかをユーザーに確認する対話モード
+use crate::replacer::Substitution;
+use crate::rules::{Rule, Step};
+use crate::syntax::Language;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::path::Path;
+use text_colorizer::*;
+
+/// 1つのマッチ箇所に対するユーザーの判断
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// この箇所を置換する
+    Accept,
+    /// この箇所は置換しない
+    Skip,
+    /// この箇所とファイル内の残りの箇所を全て置換する
+    AcceptAll,
+    /// この箇所以降は置換せずに終了する
+    Quit,
+    /// この箇所だけ指定した文字列で置換する
+    Edit(String),
+}
+
+/// 対話的な置換の結果
+#[derive(Debug, PartialEq, Eq)]
+pub struct Outcome {
+    /// 承認された箇所だけを置換したテキスト
+    pub text: String,
+    /// 承認されて置換した箇所
+    pub substitutions: Vec<Substitution>,
+    /// 途中で終了が選ばれたかどうか
+    pub quit: bool,
+}
+
+/// ruleのマッチ箇所ごとにdecideで判断を仰ぎ、承認された箇所だけを置換する
+/// decideにはマッチ箇所の範囲と、テンプレートを展開した置換後の文字列が渡される
+/// languageは範囲が指定されたルールのためにtextを字句解析する言語
+/// indexは置換した箇所に記録するルールの番号
+pub fn replace_with<F>(
+    rule: &Rule,
+    index: usize,
+    text: &str,
+    language: Option<Language>,
+    mut decide: F,
+) -> Outcome
+where
+    F: FnMut(Range<usize>, &str) -> Decision,
+{
+    let mut accept_all = false;
+    let substituted = rule.substitute(index, text, language, |range, expanded| {
+        if accept_all {
+            return Step::Replace(expanded);
+        }
+        match decide(range, &expanded) {
+            Decision::Accept => Step::Replace(expanded),
+            Decision::AcceptAll => {
+                accept_all = true;
+                Step::Replace(expanded)
+            }
+            Decision::Edit(edited) => Step::Replace(edited),
+            Decision::Skip => Step::Keep,
+            Decision::Quit => Step::Stop,
+        }
+    });
+    Outcome {
+        text: substituted.text,
+        substitutions: substituted.substitutions,
+        quit: substituted.stopped,
+    }
+}
+
+/// マッチ箇所を前後context行と共に表示し、置換するかどうかを標準入力から尋ねる
+/// 標準入力が閉じられた場合は終了を選んだものとして扱う
+pub fn prompt(
+    path: &Path,
+    text: &str,
+    range: Range<usize>,
+    expanded: &str,
+    context: usize,
+) -> Decision {
+    show_match(path, text, range, expanded, context);
+    let stdin = io::stdin();
+    loop {
+        print!("{} ", "Replace? [y,n,a,q,e,?]".blue().bold());
+        io::stdout().flush().ok();
+        let answer = match read_line(&stdin) {
+            Some(v) => v,
+            None => return Decision::Quit,
+        };
+        match answer.trim() {
+            "y" => return Decision::Accept,
+            "n" => return Decision::Skip,
+            "a" => return Decision::AcceptAll,
+            "q" => return Decision::Quit,
+            "e" => {
+                print!("{} ", "replacement>".blue().bold());
+                io::stdout().flush().ok();
+                return match read_line(&stdin) {
+                    Some(v) => Decision::Edit(v.trim_end_matches(['\r', '\n']).to_string()),
+                    None => Decision::Quit,
+                };
+            }
+            _ => {
+                println!("y - replace this match");
+                println!("n - do not replace this match");
+                println!("a - replace this and all remaining matches in the file");
+                println!("q - quit; do not replace this or any remaining match");
+                println!("e - type a replacement for this match only");
+            }
+        }
+    }
+}
+
+fn read_line(stdin: &io::Stdin) -> Option<String> {
+    let mut line = String::new();
+    match stdin.lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+/// マッチ箇所を含む行を、置換前と置換後のそれぞれでハイライトして表示する
+fn show_match(path: &Path, text: &str, range: Range<usize>, expanded: &str, context: usize) {
+    let line_start = text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[range.end..]
+        .find('\n')
+        .map_or(text.len(), |i| range.end + i);
+    let line_number = text[..range.start].matches('\n').count() + 1;
+    let before: Vec<&str> = text[..line_start].lines().collect();
+    let after = text[line_end..]
+        .get(1..)
+        .unwrap_or("")
+        .lines()
+        .take(context);
+
+    println!();
+    println!("{}:{}", path.display().to_string().bold(), line_number);
+    for line in &before[before.len().saturating_sub(context)..] {
+        println!("  {}", line);
+    }
+    let prefix = &text[line_start..range.start];
+    let suffix = &text[range.end..line_end];
+    println!(
+        "{} {}{}{}",
+        "-".red(),
+        prefix,
+        text[range].red().bold(),
+        suffix
+    );
+    println!(
+        "{} {}{}{}",
+        "+".green(),
+        prefix,
+        expanded.green().bold(),
+        suffix
+    );
+    for line in after {
+        println!("  {}", line);
+    }
+}
+
+#[test]
+fn test_replace_with() {
+    use crate::pattern::PatternOptions;
+    let rule = Rule::new(r"(\w+)@", "<$1>", PatternOptions::default()).unwrap();
+    let text = "a@ b@ c@ d@";
+    let mut answers = vec![
+        Decision::Accept,
+        Decision::Skip,
+        Decision::Edit("X".to_string()),
+        Decision::Quit,
+    ]
+    .into_iter();
+    let outcome = replace_with(&rule, 1, text, None, |_, _| answers.next().unwrap());
+    assert_eq!(outcome.text, "<a> b@ X d@");
+    assert!(outcome.quit);
+    let spans: Vec<_> = outcome
+        .substitutions
+        .iter()
+        .map(|s| (s.rule, s.span.clone(), s.output_span.clone()))
+        .collect();
+    assert_eq!(spans, vec![(1, 0..2, 0..3), (1, 6..8, 7..8)]);
+
+    let mut asked = 0;
+    let outcome = replace_with(&rule, 0, text, None, |_, _| {
+        asked += 1;
+        if asked == 1 {
+            Decision::Skip
+        } else {
+            Decision::AcceptAll
+        }
+    });
+    assert_eq!(asked, 2);
+    assert_eq!(outcome.text, "a@ <b> <c> <d>");
+    assert_eq!(outcome.substitutions.len(), 3);
+}
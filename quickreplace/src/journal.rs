@@ -0,0 +1,331 @@
+//! `--in-place`で書き換えたファイルを元に戻すための記録(ジャーナル)を扱うモジュール
+//!
+//! 1回の実行ごとに`.quickreplace/<run-id>.json`へ、書き換えた各ファイルの
+//! 書き換え前後のハッシュと、書き換えた範囲と元の内容を記録する
+//! `quickreplace --undo`は書き換え後に変更されていないファイルだけを元に戻す
+//!
+//! `.quickreplace`はカレントディレクトリではなく、find_journal_dirで親を辿って決める
+//! そのため同じリポジトリの中なら、どのサブディレクトリで実行しても同じ記録を使う
+use crate::rules::TextEdit;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use similar::{Algorithm, DiffTag};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// ジャーナルを保存するディレクトリの名前
+/// 隠しディレクトリなのでファイル探索の対象にはならない
+pub const JOURNAL_DIR: &str = ".quickreplace";
+
+/// startから親へ辿り、ジャーナルを保存するディレクトリを決める
+/// JOURNAL_DIRのあるディレクトリか、gitリポジトリのルートのうち最初に見つかったものの
+/// JOURNAL_DIRを使い、どちらも無ければstartのJOURNAL_DIRを使う
+pub fn find_journal_dir(start: &Path) -> PathBuf {
+    start
+        .ancestors()
+        .map(|dir| (dir.join(JOURNAL_DIR), dir))
+        .find(|(journal, dir)| journal.is_dir() || dir.join(".git").exists())
+        .map_or_else(|| start.join(JOURNAL_DIR), |(journal, _)| journal)
+}
+
+/// カレントディレクトリからfind_journal_dirで決めた、ジャーナルを保存するディレクトリ
+pub fn journal_dir() -> io::Result<PathBuf> {
+    Ok(find_journal_dir(&std::env::current_dir()?))
+}
+
+/// 記録する内容の断片
+/// UTF-8のテキストは読みやすいように文字列で、それ以外はバイト列の配列で記録する
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Chunk {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl Chunk {
+    fn new(data: &[u8]) -> Chunk {
+        match std::str::from_utf8(data) {
+            Ok(text) => Chunk::Text(text.to_string()),
+            Err(_) => Chunk::Bytes(data.to_vec()),
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Chunk::Text(text) => text.as_bytes(),
+            Chunk::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+/// 1か所の書き換え
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    /// 書き換え後のファイルでの開始位置(バイト)
+    pub start: usize,
+    /// 書き換え後のファイルでの終了位置(バイト)
+    pub end: usize,
+    /// 書き換え前の内容
+    pub original: Chunk,
+    /// 書き換え後の内容
+    pub replaced: Chunk,
+}
+
+/// 1ファイルの書き換えの記録
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    /// 書き換えたファイルの絶対パス
+    pub path: PathBuf,
+    /// 書き換え前の内容のSHA-256
+    pub original_hash: String,
+    /// 書き換え後の内容のSHA-256
+    pub new_hash: String,
+    /// 書き換えた範囲の一覧で、開始位置の順に並ぶ
+    pub edits: Vec<Edit>,
+}
+
+/// 元に戻せなかった理由
+#[derive(Debug, PartialEq, Eq)]
+pub enum Conflict {
+    /// 書き換えた後にファイルが変更されている
+    Modified,
+    /// 記録から元の内容を復元できなかった
+    Corrupted,
+}
+
+impl FileEntry {
+    /// pathの内容をoriginalからreplacedへ書き換えた記録を作る
+    /// 書き換えた範囲は行単位の差分で求めるので、置換した範囲が分からない場合に使う
+    pub fn new(path: &Path, original: &[u8], replaced: &[u8]) -> FileEntry {
+        let old_lines: Vec<&[u8]> = original.split_inclusive(|&b| b == b'\n').collect();
+        let new_lines: Vec<&[u8]> = replaced.split_inclusive(|&b| b == b'\n').collect();
+        let old_offsets = line_offsets(&old_lines);
+        let new_offsets = line_offsets(&new_lines);
+
+        let edits = similar::capture_diff_slices(Algorithm::Myers, &old_lines, &new_lines)
+            .into_iter()
+            .filter(|op| op.tag() != DiffTag::Equal)
+            .map(|op| {
+                let (old, new) = (op.old_range(), op.new_range());
+                let (start, end) = (new_offsets[new.start], new_offsets[new.end]);
+                Edit {
+                    start,
+                    end,
+                    original: Chunk::new(&original[old_offsets[old.start]..old_offsets[old.end]]),
+                    replaced: Chunk::new(&replaced[start..end]),
+                }
+            })
+            .collect();
+        FileEntry {
+            path: fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            original_hash: hash(original),
+            new_hash: hash(replaced),
+            edits,
+        }
+    }
+
+    /// pathの内容をoriginalからreplacedへ書き換えた記録を、置換した範囲editsから作る
+    /// 記録は置換した箇所の数に比例した大きさになる
+    /// editsから元の内容を復元できなければ、newと同じく行単位の差分で記録する
+    pub fn from_edits(
+        path: &Path,
+        original: &[u8],
+        replaced: &[u8],
+        edits: &[TextEdit],
+    ) -> FileEntry {
+        let recorded: Option<Vec<Edit>> = edits
+            .iter()
+            .map(|edit| {
+                Some(Edit {
+                    start: edit.replaced.start,
+                    end: edit.replaced.end,
+                    original: Chunk::new(original.get(edit.original.clone())?),
+                    replaced: Chunk::new(replaced.get(edit.replaced.clone())?),
+                })
+            })
+            .collect();
+        let entry = recorded.map(|edits| FileEntry {
+            path: fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            original_hash: hash(original),
+            new_hash: hash(replaced),
+            edits,
+        });
+        match entry {
+            Some(entry) if entry.restore(replaced).as_deref() == Ok(original) => entry,
+            _ => FileEntry::new(path, original, replaced),
+        }
+    }
+
+    /// 現在の内容currentから書き換え前の内容を復元する
+    pub fn restore(&self, current: &[u8]) -> Result<Vec<u8>, Conflict> {
+        if hash(current) != self.new_hash {
+            return Err(Conflict::Modified);
+        }
+        let mut restored = Vec::with_capacity(current.len());
+        let mut position = 0;
+        for edit in &self.edits {
+            if edit.start < position || edit.end < edit.start || edit.end > current.len() {
+                return Err(Conflict::Corrupted);
+            }
+            restored.extend_from_slice(&current[position..edit.start]);
+            restored.extend_from_slice(edit.original.as_bytes());
+            position = edit.end;
+        }
+        restored.extend_from_slice(&current[position..]);
+        if hash(&restored) != self.original_hash {
+            return Err(Conflict::Corrupted);
+        }
+        Ok(restored)
+    }
+}
+
+/// 各行の開始位置と、末尾としてデータ全体の長さを返す
+fn line_offsets(lines: &[&[u8]]) -> Vec<usize> {
+    let mut offsets = Vec::with_capacity(lines.len() + 1);
+    let mut offset = 0;
+    offsets.push(0);
+    for line in lines {
+        offset += line.len();
+        offsets.push(offset);
+    }
+    offsets
+}
+
+/// dataのSHA-256を16進数の文字列で返す
+pub fn hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 1回の実行の記録
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Journal {
+    /// 実行を識別するID
+    /// 実行を開始した時刻のUNIXエポックからのナノ秒とプロセスIDからなる
+    /// 同じ秒のうちに続けて実行しても、後に始めた実行ほど時刻の部分が大きい
+    pub id: String,
+    /// 書き換えたファイルの記録
+    pub files: Vec<FileEntry>,
+}
+
+impl Journal {
+    /// 新しいIDで空の記録を作り、実行の記録を始める
+    pub fn start() -> Journal {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        Journal {
+            id: format!("{}-{}", nanos, std::process::id()),
+            files: Vec::new(),
+        }
+    }
+
+    /// ディレクトリdirの中の、記録を保存するパス
+    pub fn path(dir: &Path, id: &str) -> PathBuf {
+        dir.join(format!("{}.json", id))
+    }
+
+    /// 記録をディレクトリdirへ保存する
+    /// dirには中身をgitで管理しないための.gitignoreも置く
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        fs::create_dir_all(dir)?;
+        let gitignore = dir.join(".gitignore");
+        if !gitignore.exists() {
+            fs::write(gitignore, "*\n")?;
+        }
+        let path = Journal::path(dir, &self.id);
+        fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+/// ディレクトリdirに保存されている記録のIDのうち最も新しいものを返す
+pub fn latest_id(dir: &Path) -> io::Result<Option<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut ids = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            if let Some(stem) = path.file_stem() {
+                ids.push(stem.to_string_lossy().into_owned());
+            }
+        }
+    }
+    Ok(ids.into_iter().max_by_key(|id| sort_key(id)))
+}
+
+/// IDを新しさの順に並べるためのキー
+/// 時刻を秒で記録していた以前のIDは、ナノ秒のIDよりも古いものとして並ぶ
+fn sort_key(id: &str) -> (u128, u128) {
+    let mut numbers = id.split('-').map(|n| n.parse().unwrap_or(0));
+    (numbers.next().unwrap_or(0), numbers.next().unwrap_or(0))
+}
+
+#[test]
+fn test_restore() {
+    let original = b"foo\nbar\nbaz\nfoo\n\xff\n";
+    let replaced = b"FOO\nbar\nnew line\nbaz\n\xff\n";
+    let entry = FileEntry::new(Path::new("test.txt"), original, replaced);
+    assert_eq!(entry.edits.len(), 3);
+    assert_eq!(entry.edits[0].original, Chunk::Text("foo\n".to_string()));
+    assert_eq!(entry.edits[0].replaced, Chunk::Text("FOO\n".to_string()));
+    assert_eq!(entry.restore(replaced).unwrap(), original);
+    assert_eq!(entry.restore(b"edited"), Err(Conflict::Modified));
+
+    let json = serde_json::to_string(&entry).unwrap();
+    assert_eq!(serde_json::from_str::<FileEntry>(&json).unwrap(), entry);
+    assert_eq!(Chunk::new(b"\xff"), Chunk::Bytes(vec![0xff]));
+
+    // 置換した範囲が分かれば、変わった行全体ではなく置換した箇所だけを記録する
+    let original = b"let user_id = user_id;\n";
+    let replaced = b"let id = id;\n";
+    let edits = [
+        TextEdit {
+            original: 4..11,
+            replaced: 4..6,
+        },
+        TextEdit {
+            original: 14..21,
+            replaced: 9..11,
+        },
+    ];
+    let entry = FileEntry::from_edits(Path::new("test.rs"), original, replaced, &edits);
+    assert_eq!(entry.edits.len(), 2);
+    assert_eq!(entry.edits[1].original, Chunk::Text("user_id".to_string()));
+    assert_eq!(entry.edits[1].replaced, Chunk::Text("id".to_string()));
+    assert_eq!(entry.restore(replaced).unwrap(), original);
+    // 範囲が内容と合わなければ行単位の差分で記録する
+    let entry = FileEntry::from_edits(Path::new("test.rs"), original, replaced, &edits[..1]);
+    assert_eq!(
+        entry.edits[0].original,
+        Chunk::Text("let user_id = user_id;\n".to_string())
+    );
+    assert_eq!(entry.restore(replaced).unwrap(), original);
+    assert!(sort_key("1700000000-12") < sort_key("1700000001-3"));
+    assert!(sort_key("1700000000-12") < sort_key("1700000000000000000-3"));
+}
+
+#[test]
+fn test_find_journal_dir() {
+    let root = tempfile::tempdir().unwrap();
+    let nested = root.path().join("repo/src/deep");
+    fs::create_dir_all(&nested).unwrap();
+    // 何も無ければ開始したディレクトリに作る
+    assert_eq!(find_journal_dir(&nested), nested.join(JOURNAL_DIR));
+    // gitリポジトリの中では、サブディレクトリからでもルートの記録を使う
+    fs::create_dir(root.path().join("repo/.git")).unwrap();
+    let repository = root.path().join("repo").join(JOURNAL_DIR);
+    assert_eq!(find_journal_dir(&nested), repository);
+    // 既にある記録のディレクトリは、リポジトリのルートよりも近ければそちらを使う
+    let existing = root.path().join("repo/src").join(JOURNAL_DIR);
+    fs::create_dir(&existing).unwrap();
+    assert_eq!(find_journal_dir(&nested), existing);
+}
@@ -0,0 +1,29 @@
+//! ファイルやテキストの中の文字列を正規表現で置換するライブラリ
+//!
+//! `quickreplace`コマンドの機能を、コマンドを起動せずにビルドスクリプトなどから使えるようにする
+//! 通常は[`Replacer`]を一度だけ作り、テキスト、バイト列、ストリーム、ファイルの置換に使い回す
+pub mod diff;
+pub mod encoding;
+pub mod error;
+pub mod interactive;
+pub mod journal;
+pub mod location;
+pub mod parallel;
+pub mod pattern;
+pub mod rename;
+pub mod replacer;
+pub mod rules;
+pub mod search;
+pub mod stream;
+pub mod syntax;
+pub mod template;
+pub mod walk;
+pub mod watch;
+pub mod write;
+
+pub use error::QuickReplaceError;
+pub use location::Location;
+pub use pattern::PatternOptions;
+pub use replacer::{Replacer, Report, Substitution};
+pub use syntax::{Language, Scope};
+pub use write::WriteOptions;
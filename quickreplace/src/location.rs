@@ -0,0 +1,64 @@
+//! テキスト中の位置を行番号と列番号に変換するモジュール
+use std::fmt;
+
+/// テキスト中の位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    /// 1から始まる行番号
+    pub line: usize,
+    /// 行頭からのバイト数で数えた1から始まる列番号
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// 各行の開始位置の一覧
+/// 一度作れば、任意の位置の行番号を二分探索で求められる
+#[derive(Debug)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    /// textの各行の開始位置を調べる
+    pub fn new(text: &str) -> LineIndex {
+        LineIndex::from_bytes(text.as_bytes())
+    }
+
+    /// 文字コードが分からないバイト列dataの各行の開始位置を調べる
+    /// 列番号はテキストと同じく行頭からのバイト数で数える
+    pub fn from_bytes(data: &[u8]) -> LineIndex {
+        let mut starts = vec![0];
+        starts.extend(
+            data.iter()
+                .enumerate()
+                .filter(|(_, &b)| b == b'\n')
+                .map(|(i, _)| i + 1),
+        );
+        LineIndex { starts }
+    }
+
+    /// offsetの位置の行番号と列番号を返す
+    pub fn locate(&self, offset: usize) -> Location {
+        let line = self.starts.partition_point(|&start| start <= offset);
+        Location {
+            line,
+            column: offset - self.starts[line - 1] + 1,
+        }
+    }
+}
+
+#[test]
+fn test_locate() {
+    let index = LineIndex::new("ab\ncd\n\nef");
+    assert_eq!(index.locate(0), Location { line: 1, column: 1 });
+    assert_eq!(index.locate(2), Location { line: 1, column: 3 });
+    assert_eq!(index.locate(3), Location { line: 2, column: 1 });
+    assert_eq!(index.locate(6), Location { line: 3, column: 1 });
+    assert_eq!(index.locate(8), Location { line: 4, column: 2 });
+    assert_eq!(index.locate(9).to_string(), "4:3");
+}
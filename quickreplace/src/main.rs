@@ -1,79 +1,1076 @@
-use regex::Regex;
-use std::env;
-use std::fs;
-use text_colorizer::*;
+mod cli;
 
-fn replace(target: &str, replacement: &str, text: &str) -> Result<String, regex::Error> {
-    let regex = Regex::new(target)?;
-    Ok(regex.replace_all(text, replacement).to_string())
-}
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use cli::Cli;
+use encoding::Decoded;
+use encoding_rs::Encoding;
+use error::QuickReplaceError;
+use journal::{FileEntry, Journal};
+use location::LineIndex;
+use pattern::PatternOptions;
+use quickreplace::syntax::Language;
+use quickreplace::Substitution;
+use quickreplace::{
+    diff, encoding, error, interactive, journal, location, parallel, pattern, rename, rules,
+    search, stream, walk, watch, write,
+};
+use rules::{Rule, TextEdit};
+use search::SearchOutput;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use text_colorizer::*;
+use walk::WalkOptions;
+use write::{AtomicFile, WriteOptions};
 
-fn print_usage() {
-    eprintln!(
-        "{} - change occurences of one string into another",
-        "quickreplace".green()
-    );
-    eprintln!("Usage: quickreplace <target> <replacement> <INPUT> <OUTPUT>");
-}
+/// INPUTやOUTPUTに指定すると標準入出力を表すパス
+const STDIO: &str = "-";
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct Arguments {
     target: String,
     replacement: String,
+    rules_file: Option<String>,
     filename: String,
     output: String,
+    in_place: bool,
+    /// trueならファイルの内容ではなく、PATH...以下のファイルとディレクトリの名前を置換する
+    rename: bool,
+    search: bool,
+    search_output: SearchOutput,
+    json: bool,
+    paths: Vec<String>,
+    walk: WalkOptions,
+    pattern: PatternOptions,
+    encoding: Option<&'static Encoding>,
+    template_check: bool,
+    write: WriteOptions,
+    dry_run: bool,
+    interactive: bool,
+    stream: bool,
+    context: usize,
+    jobs: usize,
+    /// trueなら置換した後もPATH...を監視し、変更されたファイルを置換し直す
+    watch: bool,
+    /// 監視中のイベントをまとめるために待つ時間
+    debounce: Duration,
+    /// 範囲が指定されたルールのために字句解析する言語
+    /// Noneならファイルの拡張子から判定する
+    language: Option<Language>,
+    /// trueなら`--in-place`で書き換えた内容を元に戻せるように記録する
+    journal: bool,
+    /// `--undo`が指定された場合に、元に戻す実行のID
+    /// IDが省略されていれば内側がNoneになる
+    undo: Option<Option<String>>,
+}
+
+/// 引数の誤りを報告して終了する
+fn usage_error(kind: ErrorKind, message: &str) -> ! {
+    Cli::command().error(kind, message).exit()
 }
 
 fn parse_args() -> Arguments {
-    let args: Vec<String> = env::args().skip(1).collect();
-    if args.len() != 4 {
-        print_usage();
+    let cli = Cli::parse();
+    if let Some(shell) = cli.completions {
+        cli::print_completions(shell);
+        std::process::exit(error::EXIT_SUCCESS);
+    }
+    if cli.man {
+        if let Err(e) = cli::print_man_page() {
+            eprintln!(
+                "{} failed to write the man page: {}",
+                "Error:".red().bold(),
+                e
+            );
+            std::process::exit(error::EXIT_ERROR);
+        }
+        std::process::exit(error::EXIT_SUCCESS);
+    }
+
+    if let Some(run_id) = cli.undo {
+        return Arguments {
+            undo: Some(run_id),
+            ..Default::default()
+        };
+    }
+
+    let encoding = match cli.encoding.as_deref().map(encoding::parse_label) {
+        Some(Ok(v)) => v,
+        Some(Err(e)) => usage_error(ErrorKind::InvalidValue, &e),
+        None => None,
+    };
+    let backup_suffix = match (cli.backup_suffix, cli.backup) {
+        (Some(suffix), _) => Some(suffix),
+        (None, true) => Some(write::DEFAULT_BACKUP_SUFFIX.to_string()),
+        (None, false) => None,
+    };
+    let mut positional = cli.args;
+    let mut parsed = Arguments {
+        rules_file: cli.rules_file,
+        in_place: cli.in_place,
+        rename: cli.rename_paths,
+        search: cli.search,
+        search_output: if cli.count {
+            SearchOutput::Count
+        } else if cli.files_with_matches {
+            SearchOutput::FilesWithMatches
+        } else {
+            SearchOutput::Matches
+        },
+        json: cli.json,
+        walk: WalkOptions {
+            include: cli.include,
+            exclude: cli.exclude,
+            no_ignore: cli.no_ignore,
+        },
+        pattern: PatternOptions {
+            fixed_strings: cli.fixed_strings,
+            ignore_case: cli.ignore_case,
+            whole_word: cli.word,
+            multi_line: cli.multi_line,
+            dot_matches_new_line: cli.dot_all,
+            scope: cli.only,
+            nth: cli.nth.map(NonZeroUsize::get),
+            max_replacements: cli.max_replacements.map(NonZeroUsize::get),
+        },
+        language: cli.language,
+        encoding,
+        template_check: cli.template_check,
+        write: WriteOptions {
+            backup_suffix,
+            preserve_mtime: cli.preserve_mtime,
+        },
+        dry_run: cli.dry_run,
+        interactive: cli.interactive,
+        stream: cli.stream,
+        context: cli.context,
+        jobs: cli.jobs.map_or_else(parallel::default_jobs, |n| n.get()),
+        watch: cli.watch,
+        debounce: Duration::from_millis(cli.debounce),
+        journal: cli.in_place && !cli.dry_run && !cli.no_journal && !cli.watch && !cli.stream,
+        ..Default::default()
+    };
+
+    // 1行ずつの置換と監視中の置換は記録しないので、undoできないことを知らせる
+    if cli.in_place && !cli.no_journal && (cli.stream || cli.watch) {
         eprintln!(
-            "{} wrong number of arguments: expected 4, got {}.",
-            "Error:".red().bold(),
-            args.len()
+            "{} {} runs are not recorded for --undo; pass --no-journal to silence this",
+            "Warning".yellow(),
+            if cli.watch { "--watch" } else { "--stream" }
+        );
+    }
+
+    if parsed.stream && parsed.encoding.is_some_and(|e| e != encoding_rs::UTF_8) {
+        usage_error(
+            ErrorKind::ArgumentConflict,
+            "--stream only supports UTF-8 input",
         );
-        std::process::exit(1);
     }
-    Arguments {
-        target: args[0].clone(),
-        replacement: args[1].clone(),
-        filename: args[2].clone(),
-        output: args[3].clone(),
+    // ルールファイルを使う場合はtargetとreplacementを指定しない
+    // 検索モードではreplacementを指定しない
+    let rule_args = match (&parsed.rules_file, parsed.search) {
+        (Some(_), _) => 0,
+        (None, true) => 1,
+        (None, false) => 2,
+    };
+    if parsed.search {
+        if positional.len() < rule_args + 1 {
+            usage_error(
+                ErrorKind::WrongNumberOfValues,
+                &format!(
+                    "wrong number of arguments: expected at least {}, got {}",
+                    rule_args + 1,
+                    positional.len()
+                ),
+            );
+        }
+        parsed.paths = positional.split_off(rule_args);
+    } else if parsed.in_place || parsed.rename {
+        if positional.len() < rule_args + 1 {
+            usage_error(
+                ErrorKind::WrongNumberOfValues,
+                &format!(
+                    "wrong number of arguments: expected at least {}, got {}",
+                    rule_args + 1,
+                    positional.len()
+                ),
+            );
+        }
+        parsed.paths = positional.split_off(rule_args);
+        if parsed.paths.iter().any(|p| p == STDIO) {
+            usage_error(ErrorKind::InvalidValue, "--in-place cannot rewrite stdin");
+        }
+    } else if positional.len() != rule_args + 2 {
+        usage_error(
+            ErrorKind::WrongNumberOfValues,
+            &format!(
+                "wrong number of arguments: expected {}, got {}",
+                rule_args + 2,
+                positional.len()
+            ),
+        );
+    } else {
+        parsed.output = positional.pop().unwrap();
+        parsed.filename = positional.pop().unwrap();
+        if parsed.interactive && parsed.filename == STDIO {
+            usage_error(
+                ErrorKind::InvalidValue,
+                "--interactive cannot read the input from stdin",
+            );
+        }
     }
+    if rule_args > 1 {
+        parsed.replacement = positional.pop().unwrap();
+    }
+    if rule_args > 0 {
+        parsed.target = positional.pop().unwrap();
+    }
+    parsed
 }
 
-fn main() {
-    let args = parse_args();
-    let data = match fs::read_to_string(&args.filename) {
+/// 複数ファイルに対する置換結果の集計
+#[derive(Debug, Default)]
+struct Summary {
+    /// 走査したファイル数
+    scanned: usize,
+    /// 書き換えたファイル数
+    changed: usize,
+    /// 置換した箇所の合計
+    matches: usize,
+    /// ルールごとの置換した箇所の合計
+    rule_matches: Vec<usize>,
+    /// バイナリやUTF-8でないためにスキップしたファイル数
+    skipped: usize,
+    /// 読み書きに失敗したファイル数
+    failed: usize,
+    /// 書き換えたファイル
+    changed_files: Vec<PathBuf>,
+}
+
+impl Summary {
+    /// 集計結果を終了コードに対応するエラーに変換する
+    fn into_result(self) -> Result<(), QuickReplaceError> {
+        if self.failed > 0 {
+            return Err(QuickReplaceError::Failed(self.failed));
+        }
+        if self.matches == 0 {
+            return Err(QuickReplaceError::NoMatch);
+        }
+        Ok(())
+    }
+}
+
+/// pathのファイルを字句解析する言語を返す
+fn language_of(args: &Arguments, path: &Path) -> Option<Language> {
+    args.language.or_else(|| Language::from_path(path))
+}
+
+/// rulesを順番にtextへ適用し、置換後のテキストとルールごとの置換数、置換した箇所の一覧を返す
+/// 対話モードでは承認された箇所だけを置換し、終了が選ばれたらquitをtrueにする
+/// 置換した箇所の一覧は、対話モードでも承認されて置換した箇所を元のテキストでの位置で返す
+fn apply(
+    path: &Path,
+    rules: &[Rule],
+    args: &Arguments,
+    text: &str,
+    quit: &mut bool,
+) -> (String, Vec<usize>, Vec<Substitution>) {
+    let language = language_of(args, path);
+    if !args.interactive {
+        let (text, substitutions) = rules::apply_rules_report(rules, text, language);
+        let mut counts = vec![0; rules.len()];
+        for substitution in &substitutions {
+            counts[substitution.rule] += 1;
+        }
+        return (text, counts, substitutions);
+    }
+    let original = text;
+    let mut text = text.to_string();
+    let mut counts = vec![0; rules.len()];
+    let mut substitutions = Vec::new();
+    for (index, rule) in rules.iter().enumerate() {
+        let current = std::mem::take(&mut text);
+        let outcome =
+            interactive::replace_with(rule, index, &current, language, |range, expanded| {
+                interactive::prompt(path, &current, range, expanded, args.context)
+            });
+        text = outcome.text;
+        counts[index] = outcome.substitutions.len();
+        substitutions.extend(outcome.substitutions);
+        if outcome.quit {
+            *quit = true;
+            break;
+        }
+    }
+    rules::locate_in_original(&LineIndex::new(original), &mut substitutions);
+    (text, counts, substitutions)
+}
+
+/// 置換前後の内容
+struct Replaced {
+    /// 差分表示用の置換前のテキスト
+    before: String,
+    /// 差分表示用の置換後のテキスト
+    after: String,
+    /// 元の文字コードと改行コードに戻した置換後の内容
+    /// 置換が無かった場合は空
+    data: Vec<u8>,
+    /// ルールごとの置換数
+    counts: Vec<usize>,
+    /// 置換した箇所の一覧
+    /// 文字コードが判明せずバイト列のまま置換した場合は空
+    substitutions: Vec<Substitution>,
+    /// 置換前後の内容で対応する、書き換えたバイト列の範囲
+    /// ジャーナルを記録しない場合や範囲が分からない場合は空
+    byte_edits: Vec<TextEdit>,
+}
+
+/// ファイルの内容の文字コードを判定してrulesを適用する
+/// 文字コードが判明しなければバイト列のまま置換する
+/// 置換対象外の内容ならNoneを返す
+fn replace_data(
+    path: &Path,
+    data: &[u8],
+    rules: &[Rule],
+    args: &Arguments,
+    quit: &mut bool,
+) -> Result<Option<Replaced>, QuickReplaceError> {
+    if !encoding::is_utf16(data, args.encoding) && walk::is_binary(data) {
+        return Ok(None);
+    }
+    let encoding_error = |message| QuickReplaceError::Encoding {
+        path: path.display().to_string(),
+        message,
+    };
+    match encoding::decode(data, args.encoding).map_err(encoding_error)? {
+        Decoded::Text {
+            text,
+            encoding,
+            bom,
+        } => {
+            let (replaced, counts, substitutions) = apply(path, rules, args, &text, quit);
+            let edits = rules::combine_substitutions(&substitutions);
+            let (replaced, edits) = encoding::restore_line_endings(&text, replaced, &edits);
+            let data = if counts.iter().any(|&c| c > 0) {
+                encoding::encode(&replaced, encoding, bom).map_err(encoding_error)?
+            } else {
+                Vec::new()
+            };
+            let byte_edits = if args.journal && !args.dry_run && !data.is_empty() {
+                encoding::byte_edits(&text, &replaced, &edits, encoding, bom).unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            Ok(Some(Replaced {
+                before: text,
+                after: replaced,
+                data,
+                counts,
+                substitutions,
+                byte_edits,
+            }))
+        }
+        // 対話モードでは内容を表示できないものを扱わない
+        Decoded::Bytes(_) if args.interactive => Ok(None),
+        Decoded::Bytes(bytes) => {
+            let (data, substitutions) = rules::apply_rules_bytes(rules, &bytes);
+            Ok(Some(Replaced {
+                before: String::from_utf8_lossy(&bytes).into_owned(),
+                after: String::from_utf8_lossy(&data).into_owned(),
+                data,
+                counts: rules::count_by_rule(&substitutions, rules.len()),
+                // バイト列のまま置換したので、置換した範囲はそのままバイト列での範囲になる
+                byte_edits: rules::combine_substitutions(&substitutions),
+                substitutions,
+            }))
+        }
+    }
+}
+
+/// 1ファイルの処理結果
+struct FileResult {
+    /// ルールごとのマッチ数
+    counts: Vec<usize>,
+    /// 置換した箇所の一覧
+    substitutions: Vec<Substitution>,
+    /// dry-runで表示する差分
+    /// 置換が無い場合やdry-runでない場合は空
+    diff: String,
+    /// 書き換えたファイルを元に戻すための記録
+    journal: Option<FileEntry>,
+}
+
+/// 1ファイルを読み込んで置換し、マッチがあれば書き戻す
+/// dry-runの場合は書き戻す代わりに差分を作る
+/// 並列に処理しても出力が混ざらないように、ここでは何も表示しない
+/// 置換対象外のファイルならNoneを返す
+fn replace_file(
+    path: &Path,
+    rules: &[Rule],
+    args: &Arguments,
+    quit: &mut bool,
+) -> Result<Option<FileResult>, QuickReplaceError> {
+    if args.stream {
+        return stream_file(path, rules, args).map(|counts| {
+            counts.map(|counts| FileResult {
+                counts,
+                substitutions: Vec::new(),
+                diff: String::new(),
+                journal: None,
+            })
+        });
+    }
+    let data = fs::read(path).map_err(|source| QuickReplaceError::Read {
+        path: path.display().to_string(),
+        source,
+    })?;
+    let replaced = match replace_data(path, &data, rules, args, quit)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let count: usize = replaced.counts.iter().sum();
+    let mut diff = String::new();
+    let mut journal = None;
+    if count > 0 && args.dry_run {
+        let label = path.display().to_string();
+        diff = diff::unified_diff(
+            &label,
+            &label,
+            &replaced.before,
+            &replaced.after,
+            args.context,
+        );
+    } else if count > 0 {
+        write::write_atomic(path, &replaced.data, &args.write).map_err(|source| {
+            QuickReplaceError::Write {
+                path: path.display().to_string(),
+                source,
+            }
+        })?;
+        if args.journal {
+            journal = Some(if replaced.byte_edits.is_empty() {
+                FileEntry::new(path, &data, &replaced.data)
+            } else {
+                FileEntry::from_edits(path, &data, &replaced.data, &replaced.byte_edits)
+            });
+        }
+    }
+    Ok(Some(FileResult {
+        counts: replaced.counts,
+        substitutions: replaced.substitutions,
+        diff,
+        journal,
+    }))
+}
+
+/// 1ファイルを1行ずつ置換し、マッチがあれば書き戻す
+fn stream_file(
+    path: &Path,
+    rules: &[Rule],
+    args: &Arguments,
+) -> Result<Option<Vec<usize>>, QuickReplaceError> {
+    let label = || path.display().to_string();
+    let mut reader =
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|source| QuickReplaceError::Read {
+                path: label(),
+                source,
+            })?;
+    match reader.fill_buf() {
+        Ok(head) if walk::is_binary(head) => return Ok(None),
+        Ok(_) => {}
+        Err(source) => {
+            return Err(QuickReplaceError::Read {
+                path: label(),
+                source,
+            })
+        }
+    }
+    let write_error = |source| QuickReplaceError::Write {
+        path: label(),
+        source,
+    };
+    let mut output = AtomicFile::create(path, &args.write).map_err(write_error)?;
+    let counts = stream::replace_lines(rules, reader, &mut output)
+        .map_err(|e| e.into_error(&label(), &label()))?;
+    if counts.iter().any(|&c| c > 0) {
+        output.commit().map_err(write_error)?;
+    }
+    Ok(Some(counts))
+}
+
+/// 置換した箇所を1行ずつ`行:列: "置換前" -> "置換後"`の形式で表示する
+/// 複数行にまたがるマッチは`開始行:列-終了行:列`の形式で範囲を表示する
+fn print_substitutions(substitutions: &[Substitution]) {
+    for substitution in substitutions {
+        let location = if substitution.start.line == substitution.end.line {
+            substitution.start.to_string()
+        } else {
+            format!("{}-{}", substitution.start, substitution.end)
+        };
+        println!(
+            "  {}: {:?} -> {:?}",
+            location.cyan(),
+            substitution.matched,
+            substitution.replacement
+        );
+    }
+}
+
+/// PATH...以下の全てのファイルを置換し、結果をまとめて報告する
+/// `--watch`ではその後も変更を監視し続ける
+fn replace_in_place(args: &Arguments, rules: &[Rule]) -> Result<(), QuickReplaceError> {
+    let mut walk_failed = 0;
+    let files = walk::collect_files(&args.paths, &args.walk, &mut |e| {
+        walk_failed += 1;
+        print_walk_error(e);
+    })
+    .map_err(QuickReplaceError::Walk)?;
+    if args.watch {
+        return watch_paths(args, rules, &files);
+    }
+    let mut summary = replace_files(args, rules, &files)?;
+    summary.failed += walk_failed;
+    summary.into_result()
+}
+
+/// 最初にfilesを置換した後、PATH...の変更を監視し、変更されたファイルを置換し直す
+/// 失敗したファイルがあっても監視を続け、監視自体が止まった場合だけ戻る
+fn watch_paths(
+    args: &Arguments,
+    rules: &[Rule],
+    files: &[PathBuf],
+) -> Result<(), QuickReplaceError> {
+    let mut watcher =
+        watch::Watcher::new(&args.paths, args.debounce).map_err(QuickReplaceError::Watch)?;
+    let mut files = files.to_vec();
+    loop {
+        let summary = replace_files(args, rules, &files)?;
+        for file in &summary.changed_files {
+            watcher.record_write(file);
+        }
+        println!(
+            "{} waiting for changes in {} (press Ctrl-C to stop)",
+            "Watch:".green().bold(),
+            args.paths.join(", ")
+        );
+        files = Vec::new();
+        while files.is_empty() {
+            let changed = watcher.next_changes().map_err(QuickReplaceError::Watch)?;
+            // 無視設定や--include/--excludeで対象外のファイルの変更では置換しない
+            files = walk::collect_files(&args.paths, &args.walk, &mut print_walk_error)
+                .map_err(QuickReplaceError::Walk)?
+                .into_iter()
+                .filter(|file| fs::canonicalize(file).is_ok_and(|file| changed.contains(&file)))
+                .collect();
+        }
+    }
+}
+
+/// filesを置換し、ファイルごとの結果と集計を表示する
+fn replace_files(
+    args: &Arguments,
+    rules: &[Rule],
+    files: &[PathBuf],
+) -> Result<Summary, QuickReplaceError> {
+    let mut summary = Summary {
+        rule_matches: vec![0; rules.len()],
+        ..Default::default()
+    };
+    let mut journal = Journal::start();
+    let mut report = |file: &Path, result: Result<Option<FileResult>, QuickReplaceError>| {
+        summary.scanned += 1;
+        match result {
+            Ok(Some(result)) => {
+                let count: usize = result.counts.iter().sum();
+                for (total, count) in summary.rule_matches.iter_mut().zip(result.counts) {
+                    *total += count;
+                }
+                if count == 0 {
+                    return;
+                }
+                summary.changed += 1;
+                summary.matches += count;
+                summary.changed_files.push(file.to_path_buf());
+                journal.files.extend(result.journal);
+                if args.dry_run {
+                    diff::print_colored(&result.diff);
+                } else {
+                    println!("{}: {} matches", file.display(), count);
+                    print_substitutions(&result.substitutions);
+                }
+            }
+            Ok(None) => {
+                summary.skipped += 1;
+                eprintln!(
+                    "{} skipped non-text file '{}'",
+                    "Warning".yellow(),
+                    file.display()
+                );
+            }
+            Err(e) => {
+                summary.failed += 1;
+                print_error(&e);
+            }
+        }
+    };
+    if args.interactive {
+        // 対話モードは端末で1件ずつ確認するので並列にしない
+        let mut quit = false;
+        for file in files {
+            if quit {
+                break;
+            }
+            report(file, replace_file(file, rules, args, &mut quit));
+        }
+    } else {
+        // rulesの正規表現は一度だけコンパイルし、全てのスレッドで共有する
+        parallel::for_each_ordered(
+            files,
+            args.jobs,
+            |file| replace_file(file, rules, args, &mut false),
+            |index, result| report(&files[index], result),
+        );
+    }
+
+    println!(
+        "{} {} matches in {} of {} files{} ({} skipped, {} failed)",
+        "Summary:".green().bold(),
+        summary.matches,
+        summary.changed,
+        summary.scanned,
+        if args.dry_run { " would change" } else { "" },
+        summary.skipped,
+        summary.failed
+    );
+    if args.rules_file.is_some() {
+        report_rules(rules, &summary.rule_matches);
+    }
+    if !journal.files.is_empty() {
+        let dir = journal::journal_dir().map_err(|source| QuickReplaceError::Write {
+            path: journal::JOURNAL_DIR.to_string(),
+            source,
+        })?;
+        let path = journal
+            .save(&dir)
+            .map_err(|source| QuickReplaceError::Write {
+                path: Journal::path(&dir, &journal.id).display().to_string(),
+                source,
+            })?;
+        println!(
+            "{} recorded run {} in {}; revert it with 'quickreplace --undo {}'",
+            "Journal:".green().bold(),
+            journal.id,
+            path.display(),
+            journal.id
+        );
+    }
+    Ok(summary)
+}
+
+/// PATH...以下のファイルとディレクトリの名前を置換して変更する
+/// 変更後のパスが重なるなどの問題が1つでもあれば、何も変更せずに問題を報告する
+fn rename_paths(args: &Arguments, rules: &[Rule]) -> Result<(), QuickReplaceError> {
+    let mut walk_failed = 0;
+    let entries = walk::collect_entries(&args.paths, &args.walk, &mut |e| {
+        walk_failed += 1;
+        print_walk_error(e);
+    })
+    .map_err(QuickReplaceError::Walk)?;
+    let plan = rename::plan_renames(&entries, rules);
+    if !plan.problems.is_empty() {
+        for problem in &plan.problems {
+            eprintln!("{} {}", "Conflict:".red().bold(), problem);
+        }
+        return Err(QuickReplaceError::RenameConflicts(plan.problems.len()));
+    }
+
+    let mut failed = 0;
+    for rename in &plan.renames {
+        let (from, to) = (rename.from.display(), rename.to.display());
+        if !args.dry_run {
+            if let Err(source) = fs::rename(&rename.from, &rename.to) {
+                failed += 1;
+                print_error(&QuickReplaceError::Rename {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    source,
+                });
+                continue;
+            }
+        }
+        println!("{} -> {}", from, to.to_string().green());
+    }
+    println!(
+        "{} {} {} of {} paths ({} failed)",
+        "Summary:".green().bold(),
+        if args.dry_run {
+            "would rename"
+        } else {
+            "renamed"
+        },
+        plan.renames.len() - failed,
+        entries.len(),
+        failed
+    );
+    if failed + walk_failed > 0 {
+        return Err(QuickReplaceError::Failed(failed + walk_failed));
+    }
+    if plan.renames.is_empty() {
+        return Err(QuickReplaceError::NoMatch);
+    }
+    Ok(())
+}
+
+/// 記録された実行で書き換えたファイルを元に戻す
+/// 書き換えた後に変更されたファイルは元に戻さずに報告し、記録に残す
+fn undo_run(run_id: Option<&str>) -> Result<(), QuickReplaceError> {
+    let dir = journal::journal_dir().map_err(|source| QuickReplaceError::Read {
+        path: journal::JOURNAL_DIR.to_string(),
+        source,
+    })?;
+    let id = match run_id {
+        Some(id) => id.to_string(),
+        None => journal::latest_id(&dir)
+            .map_err(|source| QuickReplaceError::Read {
+                path: dir.display().to_string(),
+                source,
+            })?
+            .ok_or(QuickReplaceError::NoJournal(None))?,
+    };
+    let path = Journal::path(&dir, &id);
+    let label = path.display().to_string();
+    let data = match fs::read(&path) {
         Ok(v) => v,
-        Err(e) => {
-            eprintln!(
-                "{} failed to read from file '{}': {:?}",
-                "Error".red().bold(),
-                args.filename,
-                e
-            );
-            std::process::exit(1);
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(QuickReplaceError::NoJournal(Some(id)))
+        }
+        Err(source) => {
+            return Err(QuickReplaceError::Read {
+                path: label,
+                source,
+            })
         }
     };
-    let replaced_data = match replace(&args.target, &args.replacement, &data) {
+    let mut journal: Journal =
+        serde_json::from_slice(&data).map_err(|source| QuickReplaceError::Journal {
+            path: label.clone(),
+            source,
+        })?;
+
+    let mut restored = 0;
+    let mut remaining = Vec::new();
+    for entry in journal.files {
+        let file = entry.path.display().to_string();
+        let current = match fs::read(&entry.path) {
+            Ok(v) => v,
+            Err(source) => {
+                print_error(&QuickReplaceError::Read { path: file, source });
+                remaining.push(entry);
+                continue;
+            }
+        };
+        match entry.restore(&current) {
+            Ok(original) => {
+                if let Err(source) =
+                    write::write_atomic(&entry.path, &original, &WriteOptions::default())
+                {
+                    print_error(&QuickReplaceError::Write { path: file, source });
+                    remaining.push(entry);
+                    continue;
+                }
+                restored += 1;
+                println!("{}: restored", file);
+            }
+            Err(conflict) => {
+                let reason = match conflict {
+                    journal::Conflict::Modified => "modified after the run",
+                    journal::Conflict::Corrupted => "the journal does not match the file",
+                };
+                eprintln!(
+                    "{} not restored '{}': {}",
+                    "Conflict:".red().bold(),
+                    file,
+                    reason
+                );
+                remaining.push(entry);
+            }
+        }
+    }
+
+    println!(
+        "{} restored {} files from run {} ({} not restored)",
+        "Summary:".green().bold(),
+        restored,
+        id,
+        remaining.len()
+    );
+    if remaining.is_empty() {
+        return fs::remove_file(&path).map_err(|source| QuickReplaceError::Write {
+            path: label,
+            source,
+        });
+    }
+    // 元に戻せなかったファイルだけを残し、解消した後にもう一度undoできるようにする
+    let conflicts = remaining.len();
+    journal.files = remaining;
+    journal
+        .save(&dir)
+        .map_err(|source| QuickReplaceError::Write {
+            path: label,
+            source,
+        })?;
+    Err(QuickReplaceError::UndoConflicts(conflicts))
+}
+
+/// pathsに指定されたファイルからrulesにマッチする箇所を探して表示する
+/// `-`が指定されていれば標準入力からも探す
+fn search_paths(args: &Arguments, rules: &[Rule]) -> Result<(), QuickReplaceError> {
+    let mut found = false;
+    let mut failed = 0;
+    let paths: Vec<String> = args.paths.iter().filter(|p| *p != STDIO).cloned().collect();
+    if paths.len() < args.paths.len() {
+        found |= search_input("<stdin>", read_input(STDIO), rules, args, &mut failed);
+    }
+    if !paths.is_empty() {
+        let files = walk::collect_files(&paths, &args.walk, &mut |e| {
+            failed += 1;
+            print_walk_error(e);
+        })
+        .map_err(QuickReplaceError::Walk)?;
+        for file in &files {
+            let label = file.display().to_string();
+            found |= search_input(&label, fs::read(file), rules, args, &mut failed);
+        }
+    }
+    if failed > 0 {
+        return Err(QuickReplaceError::Failed(failed));
+    }
+    if !found {
+        return Err(QuickReplaceError::NoMatch);
+    }
+    Ok(())
+}
+
+/// 1つの入力からrulesにマッチする箇所を探して表示し、マッチがあったかどうかを返す
+/// バイナリファイルは対象にしない
+fn search_input(
+    label: &str,
+    data: io::Result<Vec<u8>>,
+    rules: &[Rule],
+    args: &Arguments,
+    failed: &mut usize,
+) -> bool {
+    let data = match data {
         Ok(v) => v,
-        Err(e) => {
-            eprintln!("{} failed to replace text: {:?}", "Error".red().bold(), e);
-            std::process::exit(1);
+        Err(source) => {
+            print_error(&QuickReplaceError::Read {
+                path: label.to_string(),
+                source,
+            });
+            *failed += 1;
+            return false;
         }
     };
-    match fs::write(&args.output, &replaced_data) {
-        Ok(_) => {}
+    if !encoding::is_utf16(&data, args.encoding) && walk::is_binary(&data) {
+        return false;
+    }
+    let text = match encoding::decode(&data, args.encoding) {
+        Ok(Decoded::Text { text, .. }) => text,
+        Ok(Decoded::Bytes(bytes)) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(message) => {
+            print_error(&QuickReplaceError::Encoding {
+                path: label.to_string(),
+                message,
+            });
+            *failed += 1;
+            return false;
+        }
+    };
+    let matches = search::find_matches(rules, &text, language_of(args, Path::new(label)));
+    search::print_matches(label, &text, &matches, args.search_output, args.json);
+    !matches.is_empty()
+}
+
+/// エラーと、その原因となったエラーを順に表示する
+fn print_error(error: &QuickReplaceError) {
+    eprintln!("{} {}", "Error:".red().bold(), error);
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        eprintln!("  caused by: {}", cause);
+        source = cause.source();
+    }
+}
+
+/// ファイルを探す途中で辿れなかったエントリを報告する
+/// 残りのファイルの処理は続ける
+fn print_walk_error(error: ignore::Error) {
+    print_error(&QuickReplaceError::Walk(error));
+}
+
+/// ルールごとのマッチ数と、一度もマッチしなかったルールを報告する
+fn report_rules(rules: &[Rule], counts: &[usize]) {
+    for (rule, count) in rules.iter().zip(counts) {
+        eprintln!("{:>6}  {}", count, rule.label());
+    }
+    for (rule, _) in rules.iter().zip(counts).filter(|(_, &count)| count == 0) {
+        eprintln!(
+            "{} rule never matched: {}",
+            "Warning".yellow(),
+            rule.label()
+        );
+    }
+}
+
+/// pathの内容を全て読み込む
+/// pathが`-`なら標準入力から読み込む
+fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    if path != STDIO {
+        return fs::read(path);
+    }
+    let mut data = Vec::new();
+    io::stdin().read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// dataをpathへアトミックに書き込む
+/// pathが`-`なら標準出力へ書き出す
+fn write_output(path: &str, data: &[u8], options: &WriteOptions) -> io::Result<()> {
+    if path != STDIO {
+        return write::write_atomic(Path::new(path), data, options);
+    }
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(data)?;
+    stdout.flush()
+}
+
+/// INPUTを1行ずつ置換してOUTPUTへ書き出す
+fn replace_stream(args: &Arguments, rules: &[Rule]) -> Result<(), QuickReplaceError> {
+    let reader: Box<dyn BufRead> = if args.filename == STDIO {
+        Box::new(io::stdin().lock())
+    } else {
+        let file = File::open(&args.filename).map_err(|source| QuickReplaceError::Read {
+            path: args.filename.clone(),
+            source,
+        })?;
+        Box::new(BufReader::new(file))
+    };
+    let stream_error = |e: stream::StreamError| e.into_error(&args.filename, &args.output);
+    let counts = if args.output == STDIO {
+        stream::replace_lines(rules, reader, &mut io::stdout().lock()).map_err(stream_error)?
+    } else {
+        let write_error = |source| QuickReplaceError::Write {
+            path: args.output.clone(),
+            source,
+        };
+        let mut output =
+            AtomicFile::create(Path::new(&args.output), &args.write).map_err(write_error)?;
+        let counts = stream::replace_lines(rules, reader, &mut output).map_err(stream_error)?;
+        output.commit().map_err(write_error)?;
+        counts
+    };
+    if args.rules_file.is_some() {
+        report_rules(rules, &counts);
+    }
+    if counts.iter().all(|&c| c == 0) {
+        return Err(QuickReplaceError::NoMatch);
+    }
+    Ok(())
+}
+
+/// 引数に従って置換や検索を行う
+fn run(args: &Arguments) -> Result<(), QuickReplaceError> {
+    if let Some(run_id) = &args.undo {
+        return undo_run(run_id.as_deref());
+    }
+    let rules = match &args.rules_file {
+        Some(path) => rules::load_rules(path, &args.pattern)?,
+        None => vec![Rule::new(
+            &args.target,
+            &args.replacement,
+            args.pattern.clone(),
+        )?],
+    };
+    if args.template_check {
+        // 全てのルールの誤りを報告し、最後の1つをエラーとして返す
+        let mut invalid = None;
+        for rule in &rules {
+            if let Err(message) = rule.check_template() {
+                let error = QuickReplaceError::Template {
+                    template: rule.label().to_string(),
+                    message,
+                };
+                if let Some(previous) = invalid.replace(error) {
+                    print_error(&previous);
+                }
+            }
+        }
+        if let Some(error) = invalid {
+            return Err(error);
+        }
+    }
+    if args.search {
+        return search_paths(args, &rules);
+    }
+    if args.stream {
+        stream::check_rules(&rules)?;
+    }
+    if args.rename {
+        return rename_paths(args, &rules);
+    }
+    if args.in_place {
+        return replace_in_place(args, &rules);
+    }
+    if args.stream {
+        return replace_stream(args, &rules);
+    }
+
+    let data = read_input(&args.filename).map_err(|source| QuickReplaceError::Read {
+        path: args.filename.clone(),
+        source,
+    })?;
+    let replaced = replace_data(Path::new(&args.filename), &data, &rules, args, &mut false)?
+        .ok_or_else(|| QuickReplaceError::NotText(args.filename.clone()))?;
+    if args.rules_file.is_some() {
+        report_rules(&rules, &replaced.counts);
+    }
+    let changed = replaced.counts.iter().any(|&c| c > 0);
+    if args.dry_run {
+        diff::print_colored(&diff::unified_diff(
+            &args.filename,
+            &args.output,
+            &replaced.before,
+            &replaced.after,
+            args.context,
+        ));
+    } else {
+        let output = if changed { &replaced.data } else { &data };
+        write_output(&args.output, output, &args.write).map_err(|source| {
+            QuickReplaceError::Write {
+                path: args.output.clone(),
+                source,
+            }
+        })?;
+    }
+    if !changed {
+        return Err(QuickReplaceError::NoMatch);
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = parse_args();
+    let code = match run(&args) {
+        Ok(()) => error::EXIT_CHANGED,
+        Err(QuickReplaceError::NoMatch) => error::EXIT_NO_MATCH,
         Err(e) => {
-            eprintln!(
-                "{} failed to write to file '{}': {:?}",
-                "Error".red().bold(),
-                args.filename,
-                e
-            );
-            std::process::exit(1);
+            print_error(&e);
+            e.exit_code()
         }
     };
+    std::process::exit(code);
 }
@@ -0,0 +1,79 @@
+//! 複数のファイルをスレッドで並列に処理するモジュール
+//! 処理は終わった順に進むが、結果は元の順番で報告するので出力は常に同じになる
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// `--jobs`を指定しなかった場合のスレッド数
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// itemsのそれぞれにworkをjobs個のスレッドで並列に適用し、
+/// 結果をitemsの順番にreportへ渡す
+/// jobsが1以下ならスレッドを作らずに順番に処理する
+pub fn for_each_ordered<T, R, W, F>(items: &[T], jobs: usize, work: W, mut report: F)
+where
+    T: Sync,
+    R: Send,
+    W: Fn(&T) -> R + Sync,
+    F: FnMut(usize, R),
+{
+    if jobs <= 1 || items.len() <= 1 {
+        for (index, item) in items.iter().enumerate() {
+            report(index, work(item));
+        }
+        return;
+    }
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    crossbeam::scope(|spawner| {
+        for _ in 0..jobs.min(items.len()) {
+            let sender = sender.clone();
+            let next = &next;
+            let work = &work;
+            spawner.spawn(move |_| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= items.len() {
+                    break;
+                }
+                if sender.send((index, work(&items[index]))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // 先に終わった結果は、それより前の結果が揃うまで取っておく
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        for (index, result) in receiver {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&expected) {
+                report(expected, result);
+                expected += 1;
+            }
+        }
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_for_each_ordered() {
+    let items: Vec<u64> = (0..100).collect();
+    for jobs in [1, 4] {
+        let mut reported = Vec::new();
+        for_each_ordered(
+            &items,
+            jobs,
+            |&n| {
+                // 後の要素ほど早く終わるようにして、順番が入れ替わっても元の順で報告されることを確かめる
+                std::thread::sleep(std::time::Duration::from_micros(100 - n));
+                n * 2
+            },
+            |index, result| reported.push((index, result)),
+        );
+        let expected: Vec<_> = items.iter().map(|&n| (n as usize, n * 2)).collect();
+        assert_eq!(reported, expected);
+    }
+}
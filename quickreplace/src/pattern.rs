@@ -0,0 +1,181 @@
+//! 検索対象のパターンとフラグから正規表現を組み立てるモジュール
+use crate::syntax::Scope;
+use regex::{Regex, RegexBuilder};
+use regex_syntax::hir::{Class, Hir, HirKind, Look};
+use regex_syntax::ParserBuilder;
+
+/// パターンの解釈方法を指定するフラグ
+#[derive(Debug, Default, Clone)]
+pub struct PatternOptions {
+    /// パターンを正規表現ではなく文字列そのものとして扱う
+    /// 改行で区切れば複数の文字列のいずれかにマッチする
+    pub fixed_strings: bool,
+    /// 大文字と小文字を区別しない
+    pub ignore_case: bool,
+    /// 単語全体に一致する場合だけマッチさせる
+    pub whole_word: bool,
+    /// `^`と`$`を各行の先頭と末尾にマッチさせる
+    pub multi_line: bool,
+    /// `.`を改行にもマッチさせる
+    pub dot_matches_new_line: bool,
+    /// 指定されていれば、ソースコードのうちこの範囲にあるマッチだけを対象にする
+    pub scope: Option<Scope>,
+    /// 指定されていれば、n番目(1から数える)より前のマッチを置換しない
+    /// max_replacementsが無ければn番目のマッチだけを置換する
+    pub nth: Option<usize>,
+    /// 指定されていれば、置換するマッチをこの数までにする
+    pub max_replacements: Option<usize>,
+}
+
+/// targetをoptionsに従って解釈した正規表現の文字列を返す
+/// 固定文字列は各文字列をエスケープした選択に変換する
+/// 空行は全ての位置にマッチしてしまうので除き、空行しか無ければエラーにする
+fn pattern_string(target: &str, options: &PatternOptions) -> Result<String, regex::Error> {
+    let pattern = if options.fixed_strings {
+        let strings: Vec<_> = target
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(regex::escape)
+            .collect();
+        if strings.is_empty() {
+            return Err(regex::Error::Syntax(
+                "no non-empty fixed strings".to_string(),
+            ));
+        }
+        strings.join("|")
+    } else {
+        target.to_string()
+    };
+    if options.whole_word {
+        Ok(format!(r"\b(?:{})\b", pattern))
+    } else {
+        Ok(pattern)
+    }
+}
+
+/// targetをoptionsに従って解釈した正規表現を返す
+/// regexクレートはリテラルの選択をAho-Corasickなどの複数文字列検索で処理するので、
+/// 固定文字列が多数あっても高速に検索できる
+pub fn build_regex(target: &str, options: &PatternOptions) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&pattern_string(target, options)?)
+        .case_insensitive(options.ignore_case)
+        .multi_line(options.multi_line)
+        .dot_matches_new_line(options.dot_matches_new_line)
+        .build()
+}
+
+/// build_regexと同じ解釈で、UTF-8として不正なバイト列にも使える正規表現を返す
+pub fn build_bytes_regex(
+    target: &str,
+    options: &PatternOptions,
+) -> Result<regex::bytes::Regex, regex::Error> {
+    regex::bytes::RegexBuilder::new(&pattern_string(target, options)?)
+        .case_insensitive(options.ignore_case)
+        .multi_line(options.multi_line)
+        .dot_matches_new_line(options.dot_matches_new_line)
+        .build()
+}
+
+/// targetが改行を含む文字列にマッチし得るか、入力全体の先頭や末尾を表す`\A`や`\z`、
+/// multi_lineでない`^`と`$`を含むかどうかを返す
+/// どちらでもなければ、1行ずつ区切って置換しても全体を置換した場合と同じ結果になる
+/// 構文解析に失敗した場合は安全側に倒してtrueを返す
+pub fn can_match_newline(target: &str, options: &PatternOptions) -> bool {
+    let Ok(pattern) = pattern_string(target, options) else {
+        return true;
+    };
+    let hir = ParserBuilder::new()
+        .case_insensitive(options.ignore_case)
+        .multi_line(options.multi_line)
+        .dot_matches_new_line(options.dot_matches_new_line)
+        .build()
+        .parse(&pattern);
+    match hir {
+        Ok(hir) => hir_matches_newline(&hir),
+        Err(_) => true,
+    }
+}
+
+fn hir_matches_newline(hir: &Hir) -> bool {
+    match hir.kind() {
+        // 1行ずつ置換すると、入力全体の先頭と末尾が各行の先頭と末尾になってしまう
+        HirKind::Look(Look::Start | Look::End) => true,
+        HirKind::Empty | HirKind::Look(_) => false,
+        HirKind::Literal(literal) => literal.0.contains(&b'\n'),
+        HirKind::Class(Class::Unicode(class)) => class
+            .ranges()
+            .iter()
+            .any(|r| r.start() <= '\n' && '\n' <= r.end()),
+        HirKind::Class(Class::Bytes(class)) => class
+            .ranges()
+            .iter()
+            .any(|r| r.start() <= b'\n' && b'\n' <= r.end()),
+        HirKind::Repetition(repetition) => hir_matches_newline(&repetition.sub),
+        HirKind::Capture(capture) => hir_matches_newline(&capture.sub),
+        HirKind::Concat(hirs) | HirKind::Alternation(hirs) => hirs.iter().any(hir_matches_newline),
+    }
+}
+
+#[test]
+fn test_build_regex() {
+    let fixed = PatternOptions {
+        fixed_strings: true,
+        ..Default::default()
+    };
+    let regex = build_regex("a.b\n(", &fixed).unwrap();
+    assert!(regex.is_match("a.b"));
+    assert!(!regex.is_match("axb"));
+    assert!(regex.is_match("f("));
+    // 空行は全ての位置にマッチする選択にしない
+    let regex = build_regex("a\n\nb\n", &fixed).unwrap();
+    assert_eq!(regex.replace_all("abc\n", "X"), "XXc\n");
+    assert!(build_regex("\n\n", &fixed).is_err());
+
+    let word = PatternOptions {
+        whole_word: true,
+        ignore_case: true,
+        ..Default::default()
+    };
+    let regex = build_regex("foo", &word).unwrap();
+    assert_eq!(regex.find_iter("FOO foobar Foo").count(), 2);
+
+    let lines = PatternOptions {
+        multi_line: true,
+        dot_matches_new_line: true,
+        ..Default::default()
+    };
+    let regex = build_regex("^b.c$", &lines).unwrap();
+    assert!(regex.is_match("a\nb\nc\nd"));
+}
+
+#[test]
+fn test_can_match_newline() {
+    let options = PatternOptions::default();
+    assert!(!can_match_newline("foo.*bar", &options));
+    assert!(!can_match_newline(r"\bfoo\b", &options));
+    // multi_lineでない`^`は入力全体の先頭にだけマッチするので、1行ずつ置換すると結果が変わる
+    assert_eq!(
+        build_regex("^bar", &options)
+            .unwrap()
+            .replace_all("bar\nbar\n", "X"),
+        "X\nbar\n"
+    );
+    assert!(can_match_newline("^bar", &options));
+    assert!(can_match_newline("[a-z]+$", &options));
+    assert!(can_match_newline(r"\Afoo", &options));
+    assert!(can_match_newline(r"foo\z", &options));
+    assert!(!can_match_newline("(?m)^[a-z]+$", &options));
+    let lines = PatternOptions {
+        multi_line: true,
+        ..Default::default()
+    };
+    assert!(!can_match_newline("^[a-z]+$", &lines));
+    assert!(can_match_newline(r"foo\nbar", &options));
+    assert!(can_match_newline(r"foo\s+bar", &options));
+    assert!(can_match_newline("[^a]", &options));
+    let dot_all = PatternOptions {
+        dot_matches_new_line: true,
+        ..Default::default()
+    };
+    assert!(can_match_newline("foo.bar", &dot_all));
+}
@@ -0,0 +1,202 @@
+//! `--paths`でファイルとディレクトリの名前にルールを適用して名前を変更するモジュール
+//!
+//! 名前の変更は全てを計画してから行い、変更後のパスが重なる場合や
+//! 既存のファイルを上書きする場合は1つも変更しない
+//! 子の名前を親より先に変更するので、各変更は変更前の親ディレクトリの中で行える
+use crate::rules::{self, Rule};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 1つの名前の変更
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rename {
+    /// 変更前のパス
+    pub from: PathBuf,
+    /// 変更後のパス
+    /// 親ディレクトリの名前は変更前のままで、親の変更はこの後で行う
+    pub to: PathBuf,
+}
+
+/// 名前を変更できない理由
+#[derive(Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// 複数のパスが同じパスに変更される
+    Collision {
+        target: PathBuf,
+        sources: Vec<PathBuf>,
+    },
+    /// 変更後のパスに既にファイルかディレクトリがある
+    Exists { source: PathBuf, target: PathBuf },
+    /// 置換後の名前がファイル名として使えない
+    InvalidName { source: PathBuf, name: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Collision { target, sources } => {
+                let sources: Vec<String> = sources
+                    .iter()
+                    .map(|source| format!("'{}'", source.display()))
+                    .collect();
+                write!(
+                    f,
+                    "{} would all be renamed to '{}'",
+                    sources.join(", "),
+                    target.display()
+                )
+            }
+            Problem::Exists { source, target } => write!(
+                f,
+                "'{}' would overwrite the existing '{}'",
+                source.display(),
+                target.display()
+            ),
+            Problem::InvalidName { source, name } => {
+                write!(
+                    f,
+                    "'{}' would be renamed to invalid name {:?}",
+                    source.display(),
+                    name
+                )
+            }
+        }
+    }
+}
+
+/// 名前の変更の計画
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// 行う順に並べた名前の変更
+    /// 深いパスほど先に並ぶので、子の名前は親より先に変更される
+    pub renames: Vec<Rename>,
+    /// 名前を変更できない理由
+    /// 1つでもあれば、どの名前も変更してはいけない
+    pub problems: Vec<Problem>,
+}
+
+/// entriesの各ファイルとディレクトリの名前にrulesを適用し、名前の変更を計画する
+/// entriesはwalk::collect_entriesのように、親が子より先に並んでいなければならない
+/// UTF-8でない名前は変更しない
+pub fn plan_renames(entries: &[PathBuf], rules: &[Rule]) -> Plan {
+    let mut plan = Plan::default();
+    // 親の名前の変更も反映した、全ての変更を終えた後のパス
+    let mut finals: HashMap<&Path, PathBuf> = HashMap::new();
+    let mut targets: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for entry in entries {
+        let (Some(parent), Some(name)) = (entry.parent(), entry.file_name()) else {
+            continue;
+        };
+        let mut final_name = name.to_os_string();
+        if let Some(name) = name.to_str() {
+            let (replaced, _) = rules::apply_rules(rules, name, None);
+            if replaced != name && !is_valid_name(&replaced) {
+                plan.problems.push(Problem::InvalidName {
+                    source: entry.clone(),
+                    name: replaced,
+                });
+            } else if replaced != name {
+                let to = parent.join(&replaced);
+                if fs::symlink_metadata(&to).is_ok() {
+                    plan.problems.push(Problem::Exists {
+                        source: entry.clone(),
+                        target: to.clone(),
+                    });
+                }
+                plan.renames.push(Rename {
+                    from: entry.clone(),
+                    to,
+                });
+                final_name = replaced.into();
+            }
+        }
+        let final_path = finals
+            .get(parent)
+            .map_or_else(|| parent.to_path_buf(), |p| p.clone())
+            .join(final_name);
+        targets
+            .entry(final_path.clone())
+            .or_default()
+            .push(entry.clone());
+        finals.insert(entry, final_path);
+    }
+    for (target, sources) in targets {
+        if sources.len() > 1 {
+            plan.problems.push(Problem::Collision { target, sources });
+        }
+    }
+    plan.renames.sort_by(|a, b| {
+        let depth = |rename: &Rename| rename.from.components().count();
+        depth(b).cmp(&depth(a)).then_with(|| a.from.cmp(&b.from))
+    });
+    plan
+}
+
+/// nameが1つのファイル名として使えるかどうかを返す
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(std::path::is_separator)
+}
+
+#[test]
+fn test_plan_renames() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    fs::create_dir_all(root.join("old_mod/old_sub")).unwrap();
+    fs::write(root.join("old_mod/old_sub/old.rs"), "").unwrap();
+    fs::write(root.join("old_mod/keep.rs"), "").unwrap();
+    let entries = vec![
+        root.join("old_mod"),
+        root.join("old_mod/keep.rs"),
+        root.join("old_mod/old_sub"),
+        root.join("old_mod/old_sub/old.rs"),
+    ];
+    let rules = vec![Rule::new("old", "new", Default::default()).unwrap()];
+    let plan = plan_renames(&entries, &rules);
+    assert!(plan.problems.is_empty());
+    let renames: Vec<_> = plan
+        .renames
+        .iter()
+        .map(|r| {
+            (
+                r.from.strip_prefix(root).unwrap(),
+                r.to.strip_prefix(root).unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        renames,
+        vec![
+            (
+                Path::new("old_mod/old_sub/old.rs"),
+                Path::new("old_mod/old_sub/new.rs")
+            ),
+            (Path::new("old_mod/old_sub"), Path::new("old_mod/new_sub")),
+            (Path::new("old_mod"), Path::new("new_mod")),
+        ]
+    );
+
+    let rules = vec![Rule::new(r"^old\.rs$", "", Default::default()).unwrap()];
+    let plan = plan_renames(&entries, &rules);
+    assert!(plan.renames.is_empty());
+    assert_eq!(
+        plan.problems,
+        vec![Problem::InvalidName {
+            source: root.join("old_mod/old_sub/old.rs"),
+            name: String::new()
+        }]
+    );
+
+    // old.rsの変更後の名前が既存のkeep.rsと重なる
+    let rules = vec![Rule::new("old.rs", "keep.rs", Default::default()).unwrap()];
+    let mut entries = entries;
+    entries.push(root.join("old_mod/old_sub/keep.rs"));
+    fs::write(root.join("old_mod/old_sub/keep.rs"), "").unwrap();
+    let plan = plan_renames(&entries, &rules);
+    assert_eq!(plan.problems.len(), 2);
+    assert!(matches!(plan.problems[0], Problem::Exists { .. }));
+    assert!(
+        matches!(plan.problems[1], Problem::Collision { ref sources, .. } if sources.len() == 2)
+    );
+}
@@ -0,0 +1,274 @@
+//! ビルドスクリプトなどからコマンドを起動せずに置換するためのAPI
+use crate::encoding::{self, Decoded};
+use crate::error::QuickReplaceError;
+use crate::location::Location;
+use crate::pattern::PatternOptions;
+use crate::rules::{self, Rule};
+use crate::stream;
+use crate::syntax::Language;
+use crate::walk;
+use crate::write::{self, WriteOptions};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// 1か所の置換
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Substitution {
+    /// 置換したルールの番号
+    pub rule: usize,
+    /// ルールを適用したテキストでのマッチの範囲
+    /// ルールは順番に適用されるので、2番目以降のルールでは前のルールを適用した後のテキストでの範囲になる
+    pub span: Range<usize>,
+    /// マッチの開始位置の、元のテキストでの行番号と列番号
+    /// 前のルールで書き換えた範囲にかかるマッチでは、その範囲の元の先頭になる
+    /// 列番号は文字数ではなく行頭からのバイト数で数える
+    pub start: Location,
+    /// マッチの終了位置の、元のテキストでの行番号と列番号
+    /// 複数行にまたがるマッチではstartと行が異なる
+    pub end: Location,
+    /// ルールを適用した結果のテキストでの置換後の文字列の範囲
+    pub output_span: Range<usize>,
+    /// マッチした文字列
+    pub matched: String,
+    /// 置換後の文字列
+    pub replacement: String,
+}
+
+/// 置換の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// 置換後のテキスト
+    pub text: String,
+    /// 置換した箇所を、ルールの順、位置の順に並べたもの
+    pub substitutions: Vec<Substitution>,
+}
+
+/// 一度だけ正規表現をコンパイルし、何度でも置換に使えるルールの集まり
+///
+/// ```
+/// use quickreplace::{PatternOptions, Replacer};
+///
+/// let replacer = Replacer::new(r"(\w+)_id", "${1:pascal}Id", PatternOptions::default()).unwrap();
+/// let report = replacer.replace_report("user_id = group_id;");
+/// assert_eq!(report.text, "UserId = GroupId;");
+/// assert_eq!(report.substitutions[1].span, 10..18);
+/// ```
+#[derive(Debug)]
+pub struct Replacer {
+    rules: Vec<Rule>,
+    language: Option<Language>,
+}
+
+impl Replacer {
+    /// targetをreplacementへ置換するReplacerを作る
+    pub fn new(
+        target: &str,
+        replacement: &str,
+        options: PatternOptions,
+    ) -> Result<Replacer, QuickReplaceError> {
+        Ok(Replacer::from_rules(vec![Rule::new(
+            target,
+            replacement,
+            options,
+        )?]))
+    }
+
+    /// ルールファイルに書かれた全てのルールを順番に適用するReplacerを作る
+    pub fn from_rules_file(
+        path: &str,
+        defaults: &PatternOptions,
+    ) -> Result<Replacer, QuickReplaceError> {
+        Ok(Replacer::from_rules(rules::load_rules(path, defaults)?))
+    }
+
+    /// rulesを順番に適用するReplacerを作る
+    pub fn from_rules(rules: Vec<Rule>) -> Replacer {
+        Replacer {
+            rules,
+            language: None,
+        }
+    }
+
+    /// 範囲が指定されたルールのために、テキストをlanguageとして字句解析する
+    /// 指定しなければ、replace_fileではファイルの拡張子から判定する
+    pub fn language(mut self, language: Language) -> Replacer {
+        self.language = Some(language);
+        self
+    }
+
+    /// 適用するルール
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// textの全てのマッチを置換する
+    pub fn replace(&self, text: &str) -> String {
+        rules::apply_rules(&self.rules, text, self.language).0
+    }
+
+    /// textの全てのマッチを置換し、置換した箇所の一覧と一緒に返す
+    pub fn replace_report(&self, text: &str) -> Report {
+        self.report_in(text, self.language)
+    }
+
+    /// replace_reportと同じように置換し、範囲が指定されたルールにはlanguageを使う
+    fn report_in(&self, text: &str, language: Option<Language>) -> Report {
+        let (text, substitutions) = rules::apply_rules_report(&self.rules, text, language);
+        Report {
+            text,
+            substitutions,
+        }
+    }
+
+    /// 文字コードが分からないバイト列の全てのマッチを置換し、置換後の内容と置換した箇所の数を返す
+    /// 範囲が指定されたルールは適用しない
+    pub fn replace_bytes(&self, data: &[u8]) -> (Vec<u8>, usize) {
+        let (data, substitutions) = rules::apply_rules_bytes(&self.rules, data);
+        (data, substitutions.len())
+    }
+
+    /// readerから1行ずつ読み込んで置換し、writerへ書き出す
+    /// 置換した箇所の数を返す
+    /// 改行にマッチし得るルールや入力全体の先頭と末尾を表すルール、範囲が指定されたルールは
+    /// 全体を置換した場合と結果が変わるので、何も読み込まずにNotStreamableを返す
+    pub fn replace_lines<R: BufRead, W: Write>(
+        &self,
+        reader: R,
+        writer: &mut W,
+    ) -> Result<usize, QuickReplaceError> {
+        stream::check_rules(&self.rules)?;
+        let counts = stream::replace_lines(&self.rules, reader, writer)
+            .map_err(|e| e.into_error("<reader>", "<writer>"))?;
+        Ok(counts.iter().sum())
+    }
+
+    /// pathのファイルを置換し、マッチがあればアトミックに書き戻す
+    /// 文字コードと改行コードは元のファイルに合わせ、置換した箇所の一覧を返す
+    /// 文字コードが分からなければ、コマンドと同じくバイト列のまま置換する
+    pub fn replace_file(
+        &self,
+        path: &Path,
+        options: &WriteOptions,
+    ) -> Result<Vec<Substitution>, QuickReplaceError> {
+        let label = || path.display().to_string();
+        let data = fs::read(path).map_err(|source| QuickReplaceError::Read {
+            path: label(),
+            source,
+        })?;
+        if !encoding::is_utf16(&data, None) && walk::is_binary(&data) {
+            return Err(QuickReplaceError::NotText(label()));
+        }
+        let encoding_error = |message| QuickReplaceError::Encoding {
+            path: label(),
+            message,
+        };
+        let (data, substitutions) = match encoding::decode(&data, None).map_err(encoding_error)? {
+            Decoded::Text {
+                text,
+                encoding,
+                bom,
+            } => {
+                let language = self.language.or_else(|| Language::from_path(path));
+                let report = self.report_in(&text, language);
+                let edits = rules::combine_substitutions(&report.substitutions);
+                let (replaced, _) = encoding::restore_line_endings(&text, report.text, &edits);
+                let data = encoding::encode(&replaced, encoding, bom).map_err(encoding_error)?;
+                (data, report.substitutions)
+            }
+            Decoded::Bytes(bytes) => rules::apply_rules_bytes(&self.rules, &bytes),
+        };
+        if !substitutions.is_empty() {
+            write::write_atomic(path, &data, options).map_err(|source| {
+                QuickReplaceError::Write {
+                    path: label(),
+                    source,
+                }
+            })?;
+        }
+        Ok(substitutions)
+    }
+}
+
+#[test]
+fn test_replace_report() {
+    let replacer = Replacer::from_rules(vec![
+        Rule::new("a+", "b", PatternOptions::default()).unwrap(),
+        Rule::new("b", "[$0]", PatternOptions::default()).unwrap(),
+    ]);
+    let report = replacer.replace_report("aa-a");
+    assert_eq!(report.text, "[b]-[b]");
+    assert_eq!(replacer.replace("aa-a"), report.text);
+    let spans: Vec<_> = report
+        .substitutions
+        .iter()
+        .map(|s| (s.rule, s.span.clone(), s.output_span.clone()))
+        .collect();
+    assert_eq!(
+        spans,
+        vec![
+            (0, 0..2, 0..1),
+            (0, 3..4, 2..3),
+            (1, 0..1, 0..3),
+            (1, 2..3, 4..7)
+        ]
+    );
+
+    let (data, count) = replacer.replace_bytes(b"a\xff");
+    assert_eq!((data, count), (b"[b]\xff".to_vec(), 2));
+    let mut output = Vec::new();
+    assert_eq!(
+        replacer
+            .replace_lines("a\nc\n".as_bytes(), &mut output)
+            .unwrap(),
+        2
+    );
+    assert_eq!(output, b"[b]\nc\n");
+    assert!(matches!(
+        replacer.replace_lines(&b"\xff\n"[..], &mut Vec::new()),
+        Err(QuickReplaceError::Read { path, .. }) if path == "<reader>"
+    ));
+    // 改行にマッチし得るルールは、1行ずつ適用できない
+    let multi_line = Replacer::new(r"a\s+b", "", PatternOptions::default()).unwrap();
+    assert!(matches!(
+        multi_line.replace_lines("a\nb\n".as_bytes(), &mut Vec::new()),
+        Err(QuickReplaceError::NotStreamable(rule)) if rule == r"s/a\s+b//"
+    ));
+}
+
+#[test]
+fn test_replace_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lib.rs");
+    fs::write(&path, "fn old() {}\r\n// old\r\n").unwrap();
+    let options = PatternOptions {
+        scope: Some(crate::syntax::Scope::Code),
+        ..Default::default()
+    };
+    let replacer = Replacer::new("old", "new", options).unwrap();
+    let substitutions = replacer
+        .replace_file(&path, &WriteOptions::default())
+        .unwrap();
+    assert_eq!(substitutions.len(), 1);
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "fn new() {}\r\n// old\r\n"
+    );
+
+    // 改行コードが混ざっていても、置換していない行の改行は変えない
+    fs::write(&path, "a\r\nb\r\nc\n").unwrap();
+    let replacer = Replacer::new("a", "A\n", PatternOptions::default()).unwrap();
+    replacer
+        .replace_file(&path, &WriteOptions::default())
+        .unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "A\r\n\r\nb\r\nc\n");
+
+    // 文字コードが分からなければ、バイト列のまま置換する
+    fs::write(&path, b"a\xff a").unwrap();
+    let substitutions = replacer
+        .replace_file(&path, &WriteOptions::default())
+        .unwrap();
+    assert_eq!(substitutions.len(), 2);
+    assert_eq!(fs::read(&path).unwrap(), b"A\n\xff A\n");
+}
@@ -0,0 +1,846 @@
+//! 置換ルールと、複数のルールを記述したルールファイルを扱うモジュール
+//!
+//! ルールファイルには1行に1つ、sedと同じ`s/target/replacement/flags`形式でルールを書く
+//! 区切り文字は`s`の直後の文字で決まり、`\`でエスケープすれば区切り文字自体も書ける
+//! 空行と`#`で始まる行は無視する
+//!
+//! flagsには以下を任意に組み合わせて指定できる
+//! - `i`: 大文字と小文字を区別しない
+//! - `w`: 単語全体に一致する場合だけマッチさせる
+//! - `F`: targetを固定文字列として扱う
+//! - `m`: `^`と`$`を各行の先頭と末尾にマッチさせる
+//! - `s`: `.`を改行にもマッチさせる
+//! - `K`: ソースコードのうちコメントと文字列リテラル以外のマッチだけを置換する
+//! - `C`: ソースコードのコメントの中のマッチだけを置換する
+//! - `S`: ソースコードの文字列リテラルの中のマッチだけを置換する
+//! - `g`: sedとの互換のために受け付けるだけで、常に全てのマッチを置換する
+use crate::error::QuickReplaceError;
+use crate::location::LineIndex;
+use crate::pattern::{self, PatternOptions};
+use crate::replacer::Substitution;
+use crate::syntax::{Language, Scope, Tokens};
+use crate::template::{Group, Template};
+use regex::{Captures, Regex};
+use std::borrow::Cow;
+use std::fs;
+use std::ops::{Index, Range};
+
+/// 1つの置換ルール
+/// 正規表現とテンプレートは作る時に組み立てて確かめるので、作った後は変更できない
+#[derive(Debug)]
+pub struct Rule {
+    /// 検索対象のパターン
+    target: String,
+    /// パターンの解釈方法
+    options: PatternOptions,
+    /// targetとoptionsから組み立てた正規表現
+    regex: Regex,
+    /// 文字コードが判明しない内容に使うバイト列用の正規表現
+    bytes_regex: regex::bytes::Regex,
+    /// 置換後の文字列を組み立てるテンプレート
+    template: Template,
+    /// 報告に使うルールの表記
+    label: String,
+}
+
+/// 置換する候補の1か所ごとの扱い
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// 候補を指定した文字列で置換する
+    Replace(String),
+    /// 候補を置換せずに残す
+    Keep,
+    /// 候補とそれ以降を置換せずに終える
+    Stop,
+}
+
+/// 1つのルールをテキストに適用した結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Substituted {
+    /// 置換後のテキスト
+    pub text: String,
+    /// 置換した箇所を位置の順に並べたもの
+    pub substitutions: Vec<Substitution>,
+    /// 途中でStepのStopが選ばれたかどうか
+    pub stopped: bool,
+}
+
+/// 1行ずつ読み込む場合のように、続いた複数のテキストへ1つのルールを適用する途中の状態
+/// nthとmax_replacementsと`${#}`の番号を、テキストごとにやり直さずに続きから数える
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// これまでのテキストで見つかったマッチの数
+    matched: usize,
+    /// これまでのテキストで置換した箇所の数
+    replaced: usize,
+}
+
+/// 1か所の置換の、置換前後の内容での範囲と置換後の文字列
+struct Splice {
+    span: Range<usize>,
+    output_span: Range<usize>,
+    replacement: String,
+}
+
+/// テキストとバイト列を同じ手順で置換するための、置換対象の型
+trait Haystack: Index<Range<usize>, Output = Self> {
+    /// 置換後の内容を溜める型
+    type Buffer;
+    /// 置換対象と同じ大きさを確保した空の内容
+    fn buffer(&self) -> Self::Buffer;
+    /// 置換対象の長さ
+    fn length(&self) -> usize;
+    /// bufferの長さ
+    fn buffer_length(buffer: &Self::Buffer) -> usize;
+    /// bufferに置換対象の一部を加える
+    fn push(buffer: &mut Self::Buffer, part: &Self);
+    /// bufferに置換後の文字列を加える
+    fn push_str(buffer: &mut Self::Buffer, replacement: &str);
+}
+
+impl Haystack for str {
+    type Buffer = String;
+    fn buffer(&self) -> String {
+        String::with_capacity(self.len())
+    }
+    fn length(&self) -> usize {
+        self.len()
+    }
+    fn buffer_length(buffer: &String) -> usize {
+        buffer.len()
+    }
+    fn push(buffer: &mut String, part: &str) {
+        buffer.push_str(part);
+    }
+    fn push_str(buffer: &mut String, replacement: &str) {
+        buffer.push_str(replacement);
+    }
+}
+
+impl Haystack for [u8] {
+    type Buffer = Vec<u8>;
+    fn buffer(&self) -> Vec<u8> {
+        Vec::with_capacity(self.len())
+    }
+    fn length(&self) -> usize {
+        self.len()
+    }
+    fn buffer_length(buffer: &Vec<u8>) -> usize {
+        buffer.len()
+    }
+    fn push(buffer: &mut Vec<u8>, part: &[u8]) {
+        buffer.extend_from_slice(part);
+    }
+    fn push_str(buffer: &mut Vec<u8>, replacement: &str) {
+        buffer.extend_from_slice(replacement.as_bytes());
+    }
+}
+
+/// haystackの置換する候補ごとにdecideで扱いを決め、置換した内容と置換した箇所を返す
+/// candidatesはマッチの範囲と、何番目の置換かを受け取って置換後の文字列を展開する関数の組で、位置の順に並ぶ
+/// decideは候補の範囲と展開した文字列を受け取る
+/// 置換するたびにcounterを1つ増やし、展開する関数には増やした後の番号を渡す
+/// 3つ目の戻り値は途中でStopが選ばれたかどうか
+fn splice<H, I, E, D>(
+    haystack: &H,
+    candidates: I,
+    counter: &mut usize,
+    mut decide: D,
+) -> (H::Buffer, Vec<Splice>, bool)
+where
+    H: Haystack + ?Sized,
+    I: IntoIterator<Item = (Range<usize>, E)>,
+    E: FnOnce(usize) -> String,
+    D: FnMut(Range<usize>, String) -> Step,
+{
+    let mut output = haystack.buffer();
+    let mut splices = Vec::new();
+    let mut last = 0;
+    let mut stopped = false;
+    for (span, expand) in candidates {
+        let replacement = match decide(span.clone(), expand(*counter + 1)) {
+            Step::Replace(replacement) => replacement,
+            Step::Keep => continue,
+            Step::Stop => {
+                stopped = true;
+                break;
+            }
+        };
+        *counter += 1;
+        H::push(&mut output, &haystack[last..span.start]);
+        let start = H::buffer_length(&output);
+        H::push_str(&mut output, &replacement);
+        last = span.end;
+        splices.push(Splice {
+            span,
+            output_span: start..H::buffer_length(&output),
+            replacement,
+        });
+    }
+    H::push(&mut output, &haystack[last..haystack.length()]);
+    (output, splices, stopped)
+}
+
+/// spliceが返した置換した箇所に、ルールの番号と行番号・列番号を付ける
+/// matchedは置換前の内容のうちspanの部分を文字列にする関数
+fn located<'a>(
+    index: usize,
+    lines: &LineIndex,
+    splices: Vec<Splice>,
+    matched: impl Fn(Range<usize>) -> Cow<'a, str>,
+) -> Vec<Substitution> {
+    splices
+        .into_iter()
+        .map(|splice| Substitution {
+            rule: index,
+            start: lines.locate(splice.span.start),
+            end: lines.locate(splice.span.end),
+            matched: matched(splice.span.clone()).into_owned(),
+            span: splice.span,
+            output_span: splice.output_span,
+            replacement: splice.replacement,
+        })
+        .collect()
+}
+
+impl Rule {
+    /// targetをreplacementへ置換するルールを作る
+    /// 固定文字列のルールではreplacementをテンプレートとして解釈しない
+    pub fn new(
+        target: &str,
+        replacement: &str,
+        options: PatternOptions,
+    ) -> Result<Rule, QuickReplaceError> {
+        let regex_error = |source| QuickReplaceError::Regex {
+            pattern: target.to_string(),
+            source,
+        };
+        let regex = pattern::build_regex(target, &options).map_err(regex_error)?;
+        let bytes_regex = pattern::build_bytes_regex(target, &options).map_err(regex_error)?;
+        let template = if options.fixed_strings {
+            Template::literal(replacement)
+        } else {
+            Template::parse(replacement).map_err(|message| QuickReplaceError::Template {
+                template: replacement.to_string(),
+                message,
+            })?
+        };
+        Ok(Rule {
+            label: format!("s/{}/{}/", target, replacement),
+            target: target.to_string(),
+            options,
+            regex,
+            bytes_regex,
+            template,
+        })
+    }
+
+    /// 検索対象のパターン
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// パターンの解釈方法
+    pub fn options(&self) -> &PatternOptions {
+        &self.options
+    }
+
+    /// 報告に使うルールの表記
+    /// ルールファイルから読み込んだルールはファイル名と行番号を含む
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// テンプレートが参照するキャプチャグループが正規表現に存在するか確かめる
+    pub fn check_template(&self) -> Result<(), String> {
+        self.template.check(&self.regex)
+    }
+
+    /// 1行ずつ区切って置換しても、全体を置換した場合と同じ結果になるかどうかを返す
+    /// 範囲が指定されたルールは字句解析に前後の行が必要なので、1行ずつには置換できない
+    pub fn is_line_safe(&self) -> bool {
+        self.options.scope.is_none() && !pattern::can_match_newline(&self.target, &self.options)
+    }
+
+    /// capsの内容でテンプレートを展開する
+    /// counterは何番目の置換かを表す1から始まる番号
+    pub fn expand(&self, caps: &Captures, counter: usize) -> String {
+        self.template.render(
+            |group| {
+                let m = match group {
+                    Group::Index(index) => caps.get(*index),
+                    Group::Name(name) => caps.name(name),
+                };
+                m.map(|m| Cow::Borrowed(m.as_str()))
+            },
+            counter,
+        )
+    }
+
+    /// バイト列用の正規表現のcapsの内容でテンプレートを展開する
+    fn expand_bytes(&self, caps: &regex::bytes::Captures, counter: usize) -> String {
+        self.template.render(
+            |group| {
+                let m = match group {
+                    Group::Index(index) => caps.get(*index),
+                    Group::Name(name) => caps.name(name),
+                };
+                m.map(|m| String::from_utf8_lossy(m.as_bytes()))
+            },
+            counter,
+        )
+    }
+
+    /// nthとmax_replacementsから、置換せずに飛ばすマッチの数と置換するマッチの数を求める
+    fn window(&self) -> (usize, usize) {
+        let skip = self.options.nth.map_or(0, |n| n.saturating_sub(1));
+        let take = match (self.options.max_replacements, self.options.nth) {
+            (Some(max), _) => max,
+            (None, Some(_)) => 1,
+            (None, None) => usize::MAX,
+        };
+        (skip, take)
+    }
+
+    /// 置換するかどうかに関わらず全てのマッチの中から、nthとmax_replacementsで指定された分だけを返す
+    fn limit<I: Iterator>(&self, matches: I) -> impl Iterator<Item = I::Item> {
+        let (skip, take) = self.window();
+        matches.skip(skip).take(take)
+    }
+
+    /// textの中でこのルールが置換する箇所を順に返す
+    /// 範囲が指定されたルールでは、textをlanguageとして字句解析し、その範囲に収まるマッチだけを返す
+    /// languageが分からなければ、範囲が指定されたルールは何にもマッチしない
+    /// nthとmax_replacementsは範囲に収まるマッチだけを数える
+    pub fn captures<'t>(&self, text: &'t str, language: Option<Language>) -> Vec<Captures<'t>> {
+        let (scope, language) = match (self.options.scope, language) {
+            (None, _) => return self.limit(self.regex.captures_iter(text)).collect(),
+            (Some(scope), Some(language)) => (scope, language),
+            (Some(_), None) => return Vec::new(),
+        };
+        let tokens = Tokens::new(language, text);
+        self.limit(
+            self.regex
+                .captures_iter(text)
+                .filter(|caps| tokens.contains(scope, caps.get(0).unwrap().range())),
+        )
+        .collect()
+    }
+
+    /// textのこのルールが置換する箇所ごとにdecideで扱いを決め、置換したテキストと置換した箇所を返す
+    /// decideは箇所の範囲とテンプレートを展開した置換後の文字列を受け取る
+    /// indexは置換した箇所に記録するルールの番号で、置換した箇所の行番号と列番号はtextでの位置になる
+    /// languageは範囲が指定されたルールのためにtextを字句解析する言語
+    pub fn substitute<D>(
+        &self,
+        index: usize,
+        text: &str,
+        language: Option<Language>,
+        decide: D,
+    ) -> Substituted
+    where
+        D: FnMut(Range<usize>, String) -> Step,
+    {
+        let (output, splices, stopped) = self.splice_text(text, language, decide);
+        let lines = LineIndex::new(text);
+        Substituted {
+            text: output,
+            substitutions: located(index, &lines, splices, |span| Cow::Borrowed(&text[span])),
+            stopped,
+        }
+    }
+
+    /// textのこのルールが置換する箇所を、decideの扱いに従って置換する
+    fn splice_text<D>(
+        &self,
+        text: &str,
+        language: Option<Language>,
+        decide: D,
+    ) -> (String, Vec<Splice>, bool)
+    where
+        D: FnMut(Range<usize>, String) -> Step,
+    {
+        let candidates = self.captures(text, language).into_iter().map(|caps| {
+            let span = caps.get(0).unwrap().range();
+            (span, move |counter| self.expand(&caps, counter))
+        });
+        splice(text, candidates, &mut 0, decide)
+    }
+
+    /// dataのこのルールが置換する箇所を全て置換する
+    /// テキストとして字句解析できないので、範囲が指定されたルールは何にもマッチしない
+    fn splice_bytes(&self, data: &[u8]) -> (Vec<u8>, Vec<Splice>) {
+        if self.options.scope.is_some() {
+            return (data.to_vec(), Vec::new());
+        }
+        let candidates = self
+            .limit(self.bytes_regex.captures_iter(data))
+            .map(|caps| {
+                let span = caps.get(0).unwrap().range();
+                (span, move |counter| self.expand_bytes(&caps, counter))
+            });
+        let (output, splices, _) = splice(data, candidates, &mut 0, replace_all);
+        (output, splices)
+    }
+
+    /// textの全てのマッチを置換し、置換後のテキストとマッチ数を返す
+    pub fn replace(&self, text: &str) -> (String, usize) {
+        self.replace_in(text, None)
+    }
+
+    /// textをlanguageのソースコードとして、全てのマッチを置換する
+    pub fn replace_in(&self, text: &str, language: Option<Language>) -> (String, usize) {
+        let (replaced, splices, _) = self.splice_text(text, language, replace_all);
+        (replaced, splices.len())
+    }
+
+    /// replaceと同じくtextの全てのマッチを置換するが、nthとmax_replacementsと`${#}`の番号は
+    /// progressに記録した前のテキストまでの続きから数え、progressを進める
+    /// 範囲が指定されたルールは、replaceと同じく何にもマッチしない
+    pub fn replace_continued(&self, text: &str, progress: &mut Progress) -> (String, usize) {
+        if self.options.scope.is_some() {
+            return (text.to_string(), 0);
+        }
+        let (skip, take) = self.window();
+        let end = skip.saturating_add(take);
+        let mut matched = progress.matched;
+        let candidates = self
+            .regex
+            .captures_iter(text)
+            .map_while(|caps| {
+                if matched >= end {
+                    return None;
+                }
+                matched += 1;
+                Some((matched > skip).then_some(caps))
+            })
+            .flatten()
+            .map(|caps| {
+                let span = caps.get(0).unwrap().range();
+                (span, move |counter| self.expand(&caps, counter))
+            });
+        let (replaced, splices, _) = splice(text, candidates, &mut progress.replaced, replace_all);
+        progress.matched = matched;
+        (replaced, splices.len())
+    }
+}
+
+/// 全ての候補を展開した文字列で置換するspliceの扱い
+fn replace_all(_: Range<usize>, expanded: String) -> Step {
+    Step::Replace(expanded)
+}
+
+/// rulesを順番にtextへ適用し、置換後のテキストとルールごとのマッチ数を返す
+/// languageは範囲が指定されたルールのためにtextを字句解析する言語
+pub fn apply_rules(rules: &[Rule], text: &str, language: Option<Language>) -> (String, Vec<usize>) {
+    let mut text = text.to_string();
+    let mut counts = Vec::with_capacity(rules.len());
+    for rule in rules {
+        let (replaced, count) = rule.replace_in(&text, language);
+        if count > 0 {
+            text = replaced;
+        }
+        counts.push(count);
+    }
+    (text, counts)
+}
+
+/// apply_rules_reportと同じようにrulesを順番にバイト列へ適用する
+/// テキストとして字句解析できないので、範囲が指定されたルールは適用しない
+/// 置換した箇所のmatchedは、UTF-8として不正なバイト列を置き換え文字にしたもの
+pub fn apply_rules_bytes(rules: &[Rule], original: &[u8]) -> (Vec<u8>, Vec<Substitution>) {
+    let mut data = original.to_vec();
+    let mut substitutions = Vec::new();
+    for (index, rule) in rules.iter().enumerate() {
+        let (replaced, splices) = rule.splice_bytes(&data);
+        if splices.is_empty() {
+            continue;
+        }
+        let lines = LineIndex::from_bytes(&data);
+        substitutions.extend(located(index, &lines, splices, |span| {
+            String::from_utf8_lossy(&data[span])
+        }));
+        data = replaced;
+    }
+    locate_in_original(&LineIndex::from_bytes(original), &mut substitutions);
+    (data, substitutions)
+}
+
+/// apply_rulesと同じようにrulesを順番にtextへ適用し、置換後のテキストと置換した箇所の一覧を返す
+/// 置換した箇所は、ルールの順、位置の順に並ぶ
+pub fn apply_rules_report(
+    rules: &[Rule],
+    text: &str,
+    language: Option<Language>,
+) -> (String, Vec<Substitution>) {
+    let original = text;
+    let mut text = text.to_string();
+    let mut substitutions = Vec::new();
+    for (index, rule) in rules.iter().enumerate() {
+        let substituted = rule.substitute(index, &text, language, replace_all);
+        if substituted.substitutions.is_empty() {
+            continue;
+        }
+        text = substituted.text;
+        substitutions.extend(substituted.substitutions);
+    }
+    locate_in_original(&LineIndex::new(original), &mut substitutions);
+    (text, substitutions)
+}
+
+/// ルールを順番に適用して得た置換した箇所の一覧の行番号と列番号を、元のテキストでの位置に直す
+/// linesは元のテキストの各行の開始位置
+/// 前のルールで書き換えた範囲にかかるマッチは、その書き換えた範囲の元の先頭と末尾まで広げる
+pub fn locate_in_original(lines: &LineIndex, substitutions: &mut [Substitution]) {
+    let mut edits: Vec<TextEdit> = Vec::new();
+    let mut rest = substitutions;
+    while !rest.is_empty() {
+        let rule = rest[0].rule;
+        let length = rest.iter().take_while(|s| s.rule == rule).count();
+        let (pass, next) = rest.split_at_mut(length);
+        for substitution in pass.iter_mut() {
+            substitution.start =
+                lines.locate(original_position(&edits, substitution.span.start, false));
+            substitution.end = lines.locate(original_position(&edits, substitution.span.end, true));
+        }
+        edits = compose(&edits, pass);
+        rest = next;
+    }
+}
+
+/// editsで書き換えた後のテキストでの位置positionを、元のテキストでの位置に戻す
+/// 書き換えた範囲の内側の位置は、endがtrueなら元の範囲の末尾に、falseなら先頭に寄せる
+fn original_position(edits: &[TextEdit], position: usize, end: bool) -> usize {
+    let mut shift = 0isize;
+    for edit in edits {
+        if position <= edit.replaced.start {
+            break;
+        }
+        if position < edit.replaced.end {
+            return if end {
+                edit.original.end
+            } else {
+                edit.original.start
+            };
+        }
+        shift += edit.replaced.len() as isize - edit.original.len() as isize;
+    }
+    (position as isize - shift) as usize
+}
+
+/// 置換した箇所の一覧から、rules個のルールごとの置換した箇所の数を数える
+pub fn count_by_rule(substitutions: &[Substitution], rules: usize) -> Vec<usize> {
+    let mut counts = vec![0; rules];
+    for substitution in substitutions {
+        counts[substitution.rule] += 1;
+    }
+    counts
+}
+
+/// 全てのルールを適用する前後のテキストで対応する、書き換えた範囲
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    /// 元のテキストでの範囲
+    pub original: Range<usize>,
+    /// 全てのルールを適用した後のテキストでの範囲
+    pub replaced: Range<usize>,
+}
+
+/// apply_rules_reportが返す置換した箇所の一覧を、元のテキストと全てのルールを適用した後の
+/// テキストで対応する範囲の一覧にまとめる
+/// 後のルールのマッチが前のルールで書き換えた範囲に重なるか接していれば1つの範囲にまとめる
+/// 戻り値は位置の順に並ぶ
+pub fn combine_substitutions(substitutions: &[Substitution]) -> Vec<TextEdit> {
+    let mut edits: Vec<TextEdit> = Vec::new();
+    let mut rest = substitutions;
+    while let Some(first) = rest.first() {
+        let length = rest.iter().take_while(|s| s.rule == first.rule).count();
+        let (pass, next) = rest.split_at(length);
+        edits = compose(&edits, pass);
+        rest = next;
+    }
+    edits
+}
+
+/// 元のテキストから現在のテキストへの書き換えeditsに、現在のテキストへの1つのルールの置換passを重ねる
+fn compose(edits: &[TextEdit], pass: &[Substitution]) -> Vec<TextEdit> {
+    // 現在のテキストでの範囲と、その範囲の長さの増減を、元のテキストと次のテキストのそれぞれについて並べる
+    let mut ranges: Vec<(Range<usize>, isize, isize)> = edits
+        .iter()
+        .map(|edit| {
+            let growth = edit.replaced.len() as isize - edit.original.len() as isize;
+            (edit.replaced.clone(), growth, 0)
+        })
+        .chain(pass.iter().map(|s| {
+            let growth = s.output_span.len() as isize - s.span.len() as isize;
+            (s.span.clone(), 0, growth)
+        }))
+        .collect();
+    ranges.sort_by_key(|(range, _, _)| (range.start, range.end));
+
+    let mut combined: Vec<TextEdit> = Vec::new();
+    // 処理済みの範囲による、元のテキストと次のテキストでの位置のずれ
+    let (mut before, mut after) = (0isize, 0isize);
+    let mut iter = ranges.into_iter().peekable();
+    while let Some((range, mut grown, mut growing)) = iter.next() {
+        let (start, mut end) = (range.start, range.end);
+        while let Some((next, _, _)) = iter.peek() {
+            if next.start > end {
+                break;
+            }
+            let (next, g, h) = iter.next().unwrap();
+            end = end.max(next.end);
+            grown += g;
+            growing += h;
+        }
+        let original_start = (start as isize - before) as usize;
+        let original_end = (end as isize - before - grown) as usize;
+        let replaced_start = (start as isize + after) as usize;
+        let replaced_end = (end as isize + after + growing) as usize;
+        combined.push(TextEdit {
+            original: original_start..original_end,
+            replaced: replaced_start..replaced_end,
+        });
+        before += grown;
+        after += growing;
+    }
+    combined
+}
+
+/// `s/target/replacement/flags`形式の1行をルールに変換する
+/// flagsで指定されなかった解釈方法はdefaultsに従う
+pub fn parse_rule(line: &str, defaults: &PatternOptions) -> Result<Rule, QuickReplaceError> {
+    let syntax_error = |message: String| Err(QuickReplaceError::Syntax(message));
+    let mut chars = line.chars();
+    if chars.next() != Some('s') {
+        return syntax_error("a rule must start with 's'".to_string());
+    }
+    let delimiter = match chars.next() {
+        Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => c,
+        _ => return syntax_error("a rule needs a delimiter such as '/' after 's'".to_string()),
+    };
+
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    while parts.len() < 2 {
+        match chars.next() {
+            Some('\\') => match chars.next() {
+                Some(c) if c == delimiter => current.push(c),
+                Some(c) => {
+                    current.push('\\');
+                    current.push(c);
+                }
+                None => current.push('\\'),
+            },
+            Some(c) if c == delimiter => parts.push(std::mem::take(&mut current)),
+            Some(c) => current.push(c),
+            None => return syntax_error(format!("unterminated rule; expected '{}'", delimiter)),
+        }
+    }
+
+    let mut options = defaults.clone();
+    for flag in chars.as_str().trim_end().chars() {
+        match flag {
+            'g' => {}
+            'i' => options.ignore_case = true,
+            'w' => options.whole_word = true,
+            'F' => options.fixed_strings = true,
+            'm' => options.multi_line = true,
+            's' => options.dot_matches_new_line = true,
+            'K' => options.scope = Some(Scope::Code),
+            'C' => options.scope = Some(Scope::Comments),
+            'S' => options.scope = Some(Scope::Strings),
+            _ => return syntax_error(format!("unknown flag '{}'", flag)),
+        }
+    }
+    let mut rule = Rule::new(&parts[0], &parts[1], options)?;
+    rule.label = line.trim_end().to_string();
+    Ok(rule)
+}
+
+/// ルールファイルを読み込み、書かれている順番にルールを返す
+/// エラーメッセージにはファイル名と行番号を含める
+pub fn load_rules(path: &str, defaults: &PatternOptions) -> Result<Vec<Rule>, QuickReplaceError> {
+    let text = fs::read_to_string(path).map_err(|source| QuickReplaceError::Read {
+        path: path.to_string(),
+        source,
+    })?;
+    let mut rules = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let mut rule =
+            parse_rule(trimmed, defaults).map_err(|source| QuickReplaceError::RulesFile {
+                path: path.to_string(),
+                line: index + 1,
+                source: Box::new(source),
+            })?;
+        rule.label = format!("{}:{}: {}", path, index + 1, rule.label);
+        rules.push(rule);
+    }
+    if rules.is_empty() {
+        return Err(QuickReplaceError::NoRules(path.to_string()));
+    }
+    Ok(rules)
+}
+
+#[test]
+fn test_replace() {
+    let rule = Rule::new("world", "Rust", PatternOptions::default()).unwrap();
+    assert_eq!(
+        rule.replace("Hello, world! world"),
+        ("Hello, Rust! Rust".to_string(), 2)
+    );
+    assert_eq!(rule.replace("nothing"), ("nothing".to_string(), 0));
+
+    let rule = Rule::new(r"(\w+)_id", r"${1:pascal}Id${#}", PatternOptions::default()).unwrap();
+    assert_eq!(
+        rule.replace("user_id, group_id"),
+        ("UserId1, GroupId2".to_string(), 2)
+    );
+
+    let fixed = PatternOptions {
+        fixed_strings: true,
+        ..Default::default()
+    };
+    let rule = Rule::new("x", r"$1\U", fixed).unwrap();
+    assert_eq!(rule.replace("x"), (r"$1\U".to_string(), 1));
+}
+
+#[test]
+fn test_parse_rule() {
+    let defaults = PatternOptions::default();
+    let rule = parse_rule(r"s/foo(\d)/bar$1/g", &defaults).unwrap();
+    assert_eq!(rule.replace("foo1 foo2"), ("bar1 bar2".to_string(), 2));
+
+    let rule = parse_rule(r"s|a\|b|c|iF", &defaults).unwrap();
+    assert_eq!(rule.target, "a|b");
+    assert!(rule.options.ignore_case && rule.options.fixed_strings);
+    assert_eq!(rule.replace("A|B a"), ("c a".to_string(), 1));
+
+    assert!(parse_rule("x/a/b/", &defaults).is_err());
+    assert!(parse_rule("s/a/b", &defaults).is_err());
+    assert!(parse_rule("s/a/b/z", &defaults).is_err());
+    assert!(parse_rule("s/(/b/", &defaults).is_err());
+
+    let rule = parse_rule(r"s/\bfoo\b/bar/K", &defaults).unwrap();
+    let text = "foo(\"foo\"); // foo";
+    assert_eq!(
+        rule.replace_in(text, Some(Language::Rust)),
+        ("bar(\"foo\"); // foo".to_string(), 1)
+    );
+    assert_eq!(rule.replace_in(text, None), (text.to_string(), 0));
+    let rule = parse_rule("s/foo/bar/C", &defaults).unwrap();
+    assert_eq!(
+        rule.replace_in(text, Some(Language::Rust)),
+        ("foo(\"foo\"); // bar".to_string(), 1)
+    );
+}
+
+#[test]
+fn test_apply_rules() {
+    let defaults = PatternOptions::default();
+    let rules = vec![
+        parse_rule("s/foo/bar/", &defaults).unwrap(),
+        parse_rule("s/bar/baz/", &defaults).unwrap(),
+        parse_rule("s/never/matched/", &defaults).unwrap(),
+    ];
+    assert_eq!(
+        apply_rules(&rules, "foo bar", None),
+        ("baz baz".to_string(), vec![1, 2, 0])
+    );
+    let (data, substitutions) = apply_rules_bytes(&rules, b"foo\xff bar");
+    assert_eq!(data, b"baz\xff baz");
+    assert_eq!(count_by_rule(&substitutions, rules.len()), vec![1, 2, 0]);
+    let found: Vec<_> = substitutions
+        .iter()
+        .map(|s| (s.rule, s.start.column, s.matched.as_str()))
+        .collect();
+    assert_eq!(found, vec![(0, 1, "foo"), (1, 1, "bar"), (1, 6, "bar")]);
+}
+
+#[test]
+fn test_limits() {
+    let limited = |nth, max_replacements| {
+        let options = PatternOptions {
+            nth,
+            max_replacements,
+            ..Default::default()
+        };
+        Rule::new("a", "[$0${#}]", options).unwrap()
+    };
+    assert_eq!(
+        limited(Some(2), None).replace("aaaa"),
+        ("a[a1]aa".to_string(), 1)
+    );
+    assert_eq!(
+        limited(None, Some(2)).replace("aaaa"),
+        ("[a1][a2]aa".to_string(), 2)
+    );
+    assert_eq!(
+        limited(Some(2), Some(2)).replace("aaaa"),
+        ("a[a1][a2]a".to_string(), 2)
+    );
+    assert_eq!(
+        limited(Some(5), None).replace("aaaa"),
+        ("aaaa".to_string(), 0)
+    );
+    let (data, substitutions) = apply_rules_bytes(&[limited(Some(3), None)], b"a\xffaa");
+    assert_eq!(data, b"a\xffa[a1]");
+    assert_eq!(substitutions.len(), 1);
+}
+
+#[test]
+fn test_apply_rules_report() {
+    let options = PatternOptions {
+        dot_matches_new_line: true,
+        ..Default::default()
+    };
+    let rules = vec![Rule::new("b.c", "X", options).unwrap()];
+    let (text, substitutions) = apply_rules_report(&rules, "a\nab\ncd", None);
+    assert_eq!(text, "a\naXd");
+    assert_eq!(substitutions[0].span, 3..6);
+    assert_eq!(substitutions[0].start.to_string(), "2:2");
+    assert_eq!(substitutions[0].end.to_string(), "3:2");
+
+    // 2番目以降のルールの位置も、途中のテキストではなく元のテキストでの位置になる
+    let rules = vec![
+        Rule::new("a", "\n\n", PatternOptions::default()).unwrap(),
+        Rule::new("c|\nb", "X", PatternOptions::default()).unwrap(),
+    ];
+    let (text, substitutions) = apply_rules_report(&rules, "ab c", None);
+    assert_eq!(text, "\nX X");
+    let locations: Vec<_> = substitutions[1..]
+        .iter()
+        .map(|s| (s.span.clone(), s.start.to_string(), s.end.to_string()))
+        .collect();
+    assert_eq!(
+        locations,
+        vec![
+            (1..3, "1:1".to_string(), "1:3".to_string()),
+            (4..5, "1:4".to_string(), "1:5".to_string())
+        ]
+    );
+}
+
+#[test]
+fn test_combine_substitutions() {
+    let rules = vec![
+        Rule::new("a+", "b", PatternOptions::default()).unwrap(),
+        Rule::new("b", "[$0]", PatternOptions::default()).unwrap(),
+        Rule::new("z", "", PatternOptions::default()).unwrap(),
+    ];
+    let (text, substitutions) = apply_rules_report(&rules, "aa-b-z-a", None);
+    assert_eq!(text, "[b]-[b]--[b]");
+    let edits = combine_substitutions(&substitutions);
+    let pairs: Vec<_> = edits
+        .iter()
+        .map(|e| (&"aa-b-z-a"[e.original.clone()], &text[e.replaced.clone()]))
+        .collect();
+    assert_eq!(
+        pairs,
+        vec![("aa", "[b]"), ("b", "[b]"), ("z", ""), ("a", "[b]")]
+    );
+}
@@ -0,0 +1,134 @@
+//! 置換せずにマッチ箇所だけを一覧表示する検索モード
+use crate::location::{LineIndex, Location};
+use crate::rules::Rule;
+use crate::syntax::Language;
+use serde_json::json;
+use text_colorizer::*;
+
+/// 検索結果の表示方法
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SearchOutput {
+    /// マッチ箇所ごとに`file:line:col:`と行の内容を表示する
+    #[default]
+    Matches,
+    /// ファイルごとのマッチ数を表示する
+    Count,
+    /// マッチしたファイル名だけを表示する
+    FilesWithMatches,
+}
+
+/// 1つのマッチ箇所
+#[derive(Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// 1から始まる行番号
+    pub line: usize,
+    /// 行頭からのバイト数で数えた1から始まる列番号
+    pub column: usize,
+    /// マッチ箇所のテキスト中の開始位置
+    pub start: usize,
+    /// マッチ箇所のテキスト中の終了位置
+    pub end: usize,
+    /// マッチしたルールの番号
+    pub rule: usize,
+}
+
+/// textの中で全てのrulesにマッチする箇所を、位置の順に返す
+/// languageは範囲が指定されたルールのためにtextを字句解析する言語
+pub fn find_matches(rules: &[Rule], text: &str, language: Option<Language>) -> Vec<SearchMatch> {
+    let mut ranges: Vec<(usize, usize, usize)> = rules
+        .iter()
+        .enumerate()
+        .flat_map(|(index, rule)| {
+            rule.captures(text, language).into_iter().map(move |caps| {
+                let m = caps.get(0).unwrap();
+                (m.start(), m.end(), index)
+            })
+        })
+        .collect();
+    ranges.sort();
+
+    let lines = LineIndex::new(text);
+    ranges
+        .into_iter()
+        .map(|(start, end, rule)| {
+            let Location { line, column } = lines.locate(start);
+            SearchMatch {
+                line,
+                column,
+                start,
+                end,
+                rule,
+            }
+        })
+        .collect()
+}
+
+/// pathの検索結果をoutputの方法で標準出力に表示する
+/// jsonがtrueなら1件ごとに1行のJSONで表示する
+pub fn print_matches(
+    path: &str,
+    text: &str,
+    matches: &[SearchMatch],
+    output: SearchOutput,
+    json: bool,
+) {
+    if matches.is_empty() {
+        return;
+    }
+    match (output, json) {
+        (SearchOutput::FilesWithMatches, false) => println!("{}", path.magenta()),
+        (SearchOutput::FilesWithMatches, true) => println!("{}", json!({ "path": path })),
+        (SearchOutput::Count, false) => println!("{}:{}", path.magenta(), matches.len()),
+        (SearchOutput::Count, true) => {
+            println!("{}", json!({ "path": path, "count": matches.len() }))
+        }
+        (SearchOutput::Matches, false) => {
+            for m in matches {
+                let line_start = text[..m.start].rfind('\n').map_or(0, |i| i + 1);
+                let line_end = text[m.start..]
+                    .find('\n')
+                    .map_or(text.len(), |i| m.start + i);
+                // 複数行にまたがるマッチは最初の行の部分だけを強調する
+                let highlight_end = m.end.min(line_end);
+                println!(
+                    "{}:{}:{}:{}{}{}",
+                    path.magenta(),
+                    m.line.to_string().green(),
+                    m.column,
+                    &text[line_start..m.start],
+                    text[m.start..highlight_end].red().bold(),
+                    text[highlight_end..line_end].trim_end_matches('\r')
+                );
+            }
+        }
+        (SearchOutput::Matches, true) => {
+            for m in matches {
+                println!(
+                    "{}",
+                    json!({
+                        "path": path,
+                        "line": m.line,
+                        "column": m.column,
+                        "match": &text[m.start..m.end],
+                        "rule": m.rule,
+                    })
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_find_matches() {
+    use crate::pattern::PatternOptions;
+    let rules = vec![
+        Rule::new("foo", "", PatternOptions::default()).unwrap(),
+        Rule::new("b.r", "", PatternOptions::default()).unwrap(),
+    ];
+    let text = "foo bar\nxx foo\n\nbaz bar";
+    let found: Vec<_> = find_matches(&rules, text, None)
+        .into_iter()
+        .map(|m| (m.line, m.column, m.rule))
+        .collect();
+    assert_eq!(found, vec![(1, 1, 0), (1, 5, 1), (2, 4, 0), (4, 5, 1)]);
+}
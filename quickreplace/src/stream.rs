@@ -0,0 +1,120 @@
+//! 入力全体をメモリに読み込まず、1行ずつ置換して書き出すモジュール
+//! 巨大なログファイルやパイプからの入力でも使用メモリが行の長さで抑えられる
+use crate::error::QuickReplaceError;
+use crate::rules::{Progress, Rule};
+use std::io::{self, BufRead, Write};
+
+/// 1行ずつの置換に失敗した理由
+/// 読み込みと書き出しのどちらに失敗したかでエラーの対象のパスが変わるので区別する
+#[derive(Debug)]
+pub enum StreamError {
+    /// 入力を読み込めなかったか、UTF-8として不正だった
+    Read(io::Error),
+    /// 出力へ書き込めなかった
+    Write(io::Error),
+}
+
+impl StreamError {
+    /// 入力のパスinputか出力のパスoutputのエラーに変換する
+    pub fn into_error(self, input: &str, output: &str) -> QuickReplaceError {
+        match self {
+            StreamError::Read(source) => QuickReplaceError::Read {
+                path: input.to_string(),
+                source,
+            },
+            StreamError::Write(source) => QuickReplaceError::Write {
+                path: output.to_string(),
+                source,
+            },
+        }
+    }
+}
+
+/// rulesを1行ずつ適用しても、全体を置換した場合と同じ結果になるか確かめる
+/// そうでないルールがあれば、最初のものをエラーとして返す
+pub fn check_rules(rules: &[Rule]) -> Result<(), QuickReplaceError> {
+    match rules.iter().find(|rule| !rule.is_line_safe()) {
+        Some(rule) => Err(QuickReplaceError::NotStreamable(rule.label().to_string())),
+        None => Ok(()),
+    }
+}
+
+/// readerから1行ずつ読み込んでrulesを順番に適用し、writerへ書き出す
+/// 各行は行末のLFを除いてから置換する
+/// 全体を置換した場合と結果が変わらないよう、CRは除かずに残し、rulesはmulti_lineの`^`と`$`しか使えない
+/// rulesは事前にcheck_rulesで確かめておかなければならない
+/// nthとmax_replacementsと`${#}`の番号は、行ごとではなく入力全体で数える
+/// 戻り値はルールごとの置換した箇所の数
+pub fn replace_lines<R: BufRead, W: Write>(
+    rules: &[Rule],
+    mut reader: R,
+    writer: &mut W,
+) -> Result<Vec<usize>, StreamError> {
+    let mut line = String::new();
+    let mut counts = vec![0; rules.len()];
+    let mut progress = vec![Progress::default(); rules.len()];
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(StreamError::Read)? == 0 {
+            break;
+        }
+        let body = line.strip_suffix('\n').unwrap_or(&line);
+        let eol = &line[body.len()..];
+        let mut replaced = None;
+        for ((rule, count), progress) in rules.iter().zip(counts.iter_mut()).zip(&mut progress) {
+            let (text, matches) =
+                rule.replace_continued(replaced.as_deref().unwrap_or(body), progress);
+            if matches > 0 {
+                *count += matches;
+                replaced = Some(text);
+            }
+        }
+        let written = match replaced {
+            Some(text) => writer
+                .write_all(text.as_bytes())
+                .and_then(|_| writer.write_all(eol.as_bytes())),
+            None => writer.write_all(line.as_bytes()),
+        };
+        written.map_err(StreamError::Write)?;
+    }
+    writer.flush().map_err(StreamError::Write)?;
+    Ok(counts)
+}
+
+#[test]
+fn test_replace_lines() {
+    use crate::pattern::PatternOptions;
+    let lines = PatternOptions {
+        multi_line: true,
+        ..Default::default()
+    };
+    let rules = vec![
+        Rule::new("o+$", "0", lines.clone()).unwrap(),
+        Rule::new("^b", "B", lines).unwrap(),
+    ];
+    // 全体を置換した場合と同じく、`$`はCRの前にはマッチしない
+    let input = "foo\r\nboo\nbar\nzoo";
+    let mut output = Vec::new();
+    let counts = replace_lines(&rules, input.as_bytes(), &mut output).unwrap();
+    assert_eq!(counts, vec![2, 2]);
+    assert_eq!(String::from_utf8(output).unwrap(), "foo\r\nB0\nBar\nz0");
+
+    // `${#}`の番号とnthとmax_replacementsは行ごとにやり直さず、入力全体で数える
+    let numbered = PatternOptions {
+        nth: Some(2),
+        max_replacements: Some(3),
+        ..Default::default()
+    };
+    let rules = vec![Rule::new("a", "${#}", numbered).unwrap()];
+    let mut output = Vec::new();
+    let counts = replace_lines(&rules, "aa\na\naaa\n".as_bytes(), &mut output).unwrap();
+    assert_eq!(counts, vec![3]);
+    assert_eq!(String::from_utf8(output).unwrap(), "a1\n2\n3aa\n");
+
+    // UTF-8として不正な入力は書き出しではなく読み込みの失敗になる
+    let error = replace_lines(&rules, &b"bar\n\xff\n"[..], &mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        error.into_error("in.txt", "out.txt"),
+        QuickReplaceError::Read { path, .. } if path == "in.txt"
+    ));
+}
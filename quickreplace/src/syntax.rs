@@ -0,0 +1,329 @@
+//! ソースコードをコード・コメント・文字列リテラルに分ける簡易的な字句解析
+//! 識別子の名前を変える時に、コメントや文字列の中の同じ単語まで置換しないために使う
+//!
+//! 完全な字句解析ではなく、コメントと文字列リテラルの範囲を見つけることだけを目的にしている
+use std::ops::Range;
+use std::path::Path;
+
+/// 字句解析できる言語
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Language {
+    Rust,
+    /// C、C++、Java、JavaScript、Goなど、`//`と`/* */`のコメントを持つ言語
+    #[value(help = "C, C++, Java, JavaScript, Go and other languages with // and /* */ comments")]
+    C,
+    Python,
+}
+
+impl Language {
+    /// 拡張子から言語を判定する
+    pub fn from_path(path: &Path) -> Option<Language> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "rs" => Some(Language::Rust),
+            "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" | "java" | "js" | "jsx"
+            | "mjs" | "cjs" | "ts" | "tsx" | "go" | "cs" | "swift" | "kt" | "kts" | "scala"
+            | "dart" => Some(Language::C),
+            "py" | "pyi" | "pyw" => Some(Language::Python),
+            _ => None,
+        }
+    }
+}
+
+/// ルールがマッチできる範囲
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Scope {
+    /// コメントと文字列リテラル以外
+    #[value(help = "everything but comments and string literals")]
+    Code,
+    /// コメントの中だけ
+    #[value(help = "only inside comments")]
+    Comments,
+    /// 文字列リテラルと文字リテラルの中だけ
+    #[value(help = "only inside string and character literals")]
+    Strings,
+}
+
+/// コードでない部分の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Comment,
+    String,
+}
+
+/// テキストをコメントと文字列リテラルの範囲に分けたもの
+#[derive(Debug)]
+pub struct Tokens {
+    /// コメントと文字列リテラルの範囲で、開始位置の順に並ぶ
+    /// どちらにも含まれない部分がコード
+    spans: Vec<(Range<usize>, Kind)>,
+}
+
+impl Tokens {
+    /// textをlanguageとして字句解析する
+    pub fn new(language: Language, text: &str) -> Tokens {
+        let bytes = text.as_bytes();
+        let mut spans = Vec::new();
+        let mut i = 0;
+        while i < bytes.len() {
+            let found = match language {
+                Language::Rust => scan_rust(text, i),
+                Language::C => scan_c(text, i),
+                Language::Python => scan_python(text, i),
+            };
+            match found {
+                Some((end, kind)) => {
+                    spans.push((i..end, kind));
+                    i = end;
+                }
+                None => i += 1,
+            }
+        }
+        Tokens { spans }
+    }
+
+    /// rangeがscopeの範囲に収まっているかどうかを返す
+    /// コードの範囲にはコメントや文字列に一部でも重なるものを含めない
+    pub fn contains(&self, scope: Scope, range: Range<usize>) -> bool {
+        let kind = match scope {
+            Scope::Code => {
+                return !self
+                    .spans
+                    .iter()
+                    .any(|(span, _)| span.start < range.end && range.start < span.end)
+            }
+            Scope::Comments => Kind::Comment,
+            Scope::Strings => Kind::String,
+        };
+        // rangeを含み得るのは、開始位置がrange.start以下のうち最後の範囲だけ
+        let index = self
+            .spans
+            .partition_point(|(span, _)| span.start <= range.start);
+        index > 0 && {
+            let (span, span_kind) = &self.spans[index - 1];
+            *span_kind == kind && range.end <= span.end
+        }
+    }
+}
+
+/// iから始まるRustのコメントか文字列リテラルを探し、終了位置と種類を返す
+fn scan_rust(text: &str, i: usize) -> Option<(usize, Kind)> {
+    let bytes = text.as_bytes();
+    let rest = &bytes[i..];
+    if rest.starts_with(b"//") {
+        return Some((line_end(bytes, i), Kind::Comment));
+    }
+    if rest.starts_with(b"/*") {
+        return Some((block_comment_end(bytes, i, true), Kind::Comment));
+    }
+    // b"..."やr#"..."#などの接頭辞付きの文字列
+    let prefix = rest.iter().take_while(|b| b"brc".contains(b)).count();
+    if prefix > 0 && follows_identifier(bytes, i) {
+        return None;
+    }
+    let after = &rest[prefix..];
+    if prefix <= 2 && rest[..prefix].contains(&b'r') {
+        let hashes = after.iter().take_while(|&&b| b == b'#').count();
+        if after.get(hashes) == Some(&b'"') {
+            let mut close = vec![b'"'];
+            close.extend(std::iter::repeat_n(b'#', hashes));
+            let body = i + prefix + hashes + 1;
+            let end = find(bytes, body, &close).map_or(bytes.len(), |p| p + close.len());
+            return Some((end, Kind::String));
+        }
+        return None;
+    }
+    if prefix <= 1 && after.first() == Some(&b'"') {
+        return Some((quoted_end(bytes, i + prefix + 1, b"\"", true), Kind::String));
+    }
+    if prefix <= 1 && after.first() == Some(&b'\'') {
+        return char_literal_end(text, i + prefix).map(|end| (end, Kind::String));
+    }
+    None
+}
+
+/// iから始まるC系の言語のコメントか文字列リテラルを探し、終了位置と種類を返す
+fn scan_c(text: &str, i: usize) -> Option<(usize, Kind)> {
+    let bytes = text.as_bytes();
+    let rest = &bytes[i..];
+    if rest.starts_with(b"//") {
+        return Some((line_end(bytes, i), Kind::Comment));
+    }
+    if rest.starts_with(b"/*") {
+        return Some((block_comment_end(bytes, i, false), Kind::Comment));
+    }
+    match rest[0] {
+        b'"' => Some((quoted_end(bytes, i + 1, b"\"", false), Kind::String)),
+        b'\'' => Some((quoted_end(bytes, i + 1, b"'", false), Kind::String)),
+        // JavaScriptのテンプレート文字列とGoの生文字列は複数行にまたがる
+        b'`' => Some((quoted_end(bytes, i + 1, b"`", true), Kind::String)),
+        _ => None,
+    }
+}
+
+/// iから始まるPythonのコメントか文字列リテラルを探し、終了位置と種類を返す
+fn scan_python(text: &str, i: usize) -> Option<(usize, Kind)> {
+    let bytes = text.as_bytes();
+    if bytes[i] == b'#' {
+        return Some((line_end(bytes, i), Kind::Comment));
+    }
+    // r"..."やf'...'などの接頭辞
+    let rest = &bytes[i..];
+    let prefix = rest
+        .iter()
+        .take(2)
+        .take_while(|b| b"rRbBuUfF".contains(b))
+        .count();
+    if prefix > 0 && follows_identifier(bytes, i) {
+        return None;
+    }
+    let quote = *rest.get(prefix)?;
+    if quote != b'"' && quote != b'\'' {
+        return None;
+    }
+    let start = i + prefix;
+    let triple = [quote; 3];
+    if bytes[start..].starts_with(&triple) {
+        Some((quoted_end(bytes, start + 3, &triple, true), Kind::String))
+    } else {
+        Some((quoted_end(bytes, start + 1, &[quote], false), Kind::String))
+    }
+}
+
+/// iの直前が識別子の一部かどうかを返す
+/// `bar"`の`r`のように、識別子の末尾を文字列の接頭辞と取り違えないために使う
+fn follows_identifier(bytes: &[u8], i: usize) -> bool {
+    i > 0 && (bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_')
+}
+
+/// iを含む行の改行の位置を返す
+fn line_end(bytes: &[u8], i: usize) -> usize {
+    bytes[i..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |p| i + p)
+}
+
+/// iから始まる`/* */`コメントの終了位置を返す
+/// nestedがtrueなら入れ子のコメントを数える
+fn block_comment_end(bytes: &[u8], i: usize, nested: bool) -> usize {
+    let mut depth = 0;
+    let mut j = i;
+    while j < bytes.len() {
+        if bytes[j..].starts_with(b"/*") && (nested || depth == 0) {
+            depth += 1;
+            j += 2;
+        } else if bytes[j..].starts_with(b"*/") {
+            depth -= 1;
+            j += 2;
+            if depth == 0 {
+                return j;
+            }
+        } else {
+            j += 1;
+        }
+    }
+    bytes.len()
+}
+
+/// startから始まる文字列の本体を読み、閉じるcloseの直後の位置を返す
+/// `\`の次の文字は閉じ記号とみなさない
+/// multilineがfalseなら、閉じられていない文字列は行末で終わりとみなす
+fn quoted_end(bytes: &[u8], start: usize, close: &[u8], multiline: bool) -> usize {
+    let mut j = start;
+    while j < bytes.len() {
+        if bytes[j] == b'\\' {
+            j += 2;
+        } else if bytes[j..].starts_with(close) {
+            return j + close.len();
+        } else if bytes[j] == b'\n' && !multiline {
+            return j;
+        } else {
+            j += 1;
+        }
+    }
+    bytes.len()
+}
+
+/// iの`'`から始まるRustの文字リテラルの終了位置を返す
+/// `'a`のようなライフタイムならNoneを返す
+fn char_literal_end(text: &str, i: usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    if bytes.get(i + 1) == Some(&b'\\') {
+        return Some(quoted_end(bytes, i + 1, b"'", false));
+    }
+    let c = text[i + 1..].chars().next()?;
+    let close = i + 1 + c.len_utf8();
+    (bytes.get(close) == Some(&b'\'')).then_some(close + 1)
+}
+
+/// startより後でpatternが最初に現れる位置を返す
+fn find(bytes: &[u8], start: usize, pattern: &[u8]) -> Option<usize> {
+    bytes[start..]
+        .windows(pattern.len())
+        .position(|w| w == pattern)
+        .map(|p| start + p)
+}
+
+#[cfg(test)]
+fn spans(language: Language, text: &str) -> Vec<(&str, Kind)> {
+    Tokens::new(language, text)
+        .spans
+        .into_iter()
+        .map(|(range, kind)| (&text[range], kind))
+        .collect()
+}
+
+#[test]
+fn test_tokens_rust() {
+    let text = "let s = \"a \\\" b\"; // note\n/* x /* y */ z */ fn f<'a>(c: char) { 'x'; '\\n'; r#\"raw \"q\"\"#; b'b'; bar\"s\" }";
+    assert_eq!(
+        spans(Language::Rust, text),
+        vec![
+            ("\"a \\\" b\"", Kind::String),
+            ("// note", Kind::Comment),
+            ("/* x /* y */ z */", Kind::Comment),
+            ("'x'", Kind::String),
+            ("'\\n'", Kind::String),
+            ("r#\"raw \"q\"\"#", Kind::String),
+            ("b'b'", Kind::String),
+            ("\"s\"", Kind::String),
+        ]
+    );
+}
+
+#[test]
+fn test_tokens_c_and_python() {
+    let text = "int a = 1; /* c */ char *s = \"x\"; // end\nlet t = `multi\nline`;";
+    assert_eq!(
+        spans(Language::C, text),
+        vec![
+            ("/* c */", Kind::Comment),
+            ("\"x\"", Kind::String),
+            ("// end", Kind::Comment),
+            ("`multi\nline`", Kind::String),
+        ]
+    );
+    let text = "x = f'{a}' # c\ny = \"\"\"doc\n\"quoted\" \"\"\"\nz = rb'\\''";
+    assert_eq!(
+        spans(Language::Python, text),
+        vec![
+            ("f'{a}'", Kind::String),
+            ("# c", Kind::Comment),
+            ("\"\"\"doc\n\"quoted\" \"\"\"", Kind::String),
+            ("rb'\\''", Kind::String),
+        ]
+    );
+}
+
+#[test]
+fn test_contains() {
+    let text = "foo(\"foo\") // foo";
+    let tokens = Tokens::new(Language::Rust, text);
+    assert!(tokens.contains(Scope::Code, 0..3));
+    assert!(!tokens.contains(Scope::Code, 5..8));
+    assert!(tokens.contains(Scope::Strings, 5..8));
+    assert!(!tokens.contains(Scope::Comments, 5..8));
+    assert!(tokens.contains(Scope::Comments, 14..17));
+    assert!(!tokens.contains(Scope::Code, 2..6));
+}
@@ -0,0 +1,420 @@
+//! 置換後の文字列を組み立てるテンプレートを扱うモジュール
+//!
+//! テンプレートでは以下の記法が使える
+//! - `$1`, `${1}`, `$name`, `${name}`: キャプチャグループの内容
+//! - `${1:snake}`など: キャプチャした識別子の書き方を変換した内容
+//!   変換には`snake`, `screaming`, `kebab`, `camel`, `pascal`, `upper`, `lower`を指定できる
+//! - `${#}`: 何番目の置換かを表す1から始まる番号
+//! - `\U`, `\L`: `\E`または末尾まで大文字・小文字にする
+//! - `\u`, `\l`: 次の1文字だけ大文字・小文字にする
+//! - `$$`, `\$`, `\\`, `\n`, `\t`: それぞれ`$`, `$`, `\`, 改行, タブ
+use regex::Regex;
+use std::borrow::Cow;
+
+/// キャプチャグループの参照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Group {
+    /// 番号による参照
+    Index(usize),
+    /// 名前による参照
+    Name(String),
+}
+
+/// キャプチャした識別子の書き方の変換
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Conversion {
+    /// snake_case
+    Snake,
+    /// SCREAMING_SNAKE_CASE
+    Screaming,
+    /// kebab-case
+    Kebab,
+    /// camelCase
+    Camel,
+    /// PascalCase
+    Pascal,
+    /// 全て大文字
+    Upper,
+    /// 全て小文字
+    Lower,
+}
+
+/// 大文字・小文字の変換
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+/// テンプレートを構成する要素
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    /// そのまま出力する文字列
+    Literal(String),
+    /// キャプチャグループの内容
+    Group(Group, Option<Conversion>),
+    /// 何番目の置換かを表す番号
+    Counter,
+    /// `\U`と`\L`: 以降の文字を変換する
+    CaseStart(Case),
+    /// `\E`: `\U`と`\L`の変換を終える
+    CaseEnd,
+    /// `\u`と`\l`: 次の1文字だけ変換する
+    NextChar(Case),
+}
+
+/// 解析済みの置換テンプレート
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// テンプレート記法の文字列を解析する
+    pub fn parse(source: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            let part = match (c, chars.peek().copied()) {
+                ('$', Some('$')) | ('\\', Some('$')) => {
+                    chars.next();
+                    literal.push('$');
+                    continue;
+                }
+                ('$', Some('{')) => {
+                    chars.next();
+                    let mut inner = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => inner.push(c),
+                            None => return Err(format!("unterminated '${{{}'", inner)),
+                        }
+                    }
+                    parse_braced(&inner)?
+                }
+                ('$', Some(c)) if is_name_char(c) => {
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|&c| is_name_char(c)) {
+                        name.push(c);
+                    }
+                    Part::Group(parse_group(&name), None)
+                }
+                ('\\', Some(next)) => {
+                    chars.next();
+                    match next {
+                        'U' => Part::CaseStart(Case::Upper),
+                        'L' => Part::CaseStart(Case::Lower),
+                        'E' => Part::CaseEnd,
+                        'u' => Part::NextChar(Case::Upper),
+                        'l' => Part::NextChar(Case::Lower),
+                        '\\' => {
+                            literal.push('\\');
+                            continue;
+                        }
+                        'n' => {
+                            literal.push('\n');
+                            continue;
+                        }
+                        't' => {
+                            literal.push('\t');
+                            continue;
+                        }
+                        // 未知のエスケープは書かれた通りに出力する
+                        other => {
+                            literal.push('\\');
+                            literal.push(other);
+                            continue;
+                        }
+                    }
+                }
+                _ => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template { parts })
+    }
+
+    /// 記法を解釈せず、sourceをそのまま出力するテンプレートを作る
+    pub fn literal(source: &str) -> Template {
+        Template {
+            parts: vec![Part::Literal(source.to_string())],
+        }
+    }
+
+    /// テンプレートが参照するキャプチャグループが全てregexに存在するかを確認する
+    pub fn check(&self, regex: &Regex) -> Result<(), String> {
+        let missing: Vec<String> = self
+            .groups()
+            .filter(|group| match group {
+                Group::Index(index) => *index >= regex.captures_len(),
+                Group::Name(name) => !regex.capture_names().flatten().any(|n| n == name),
+            })
+            .map(|group| match group {
+                Group::Index(index) => format!("${}", index),
+                Group::Name(name) => format!("${{{}}}", name),
+            })
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "the replacement refers to groups that do not exist in the pattern: {}",
+                missing.join(", ")
+            ))
+        }
+    }
+
+    /// テンプレートが参照するキャプチャグループ
+    fn groups(&self) -> impl Iterator<Item = &Group> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Group(group, _) => Some(group),
+            _ => None,
+        })
+    }
+
+    /// テンプレートを展開する
+    /// groupはキャプチャグループの内容を返す関数で、マッチしなかったグループは空文字列として扱う
+    /// counterは`${#}`に出力する番号
+    pub fn render<'a, F>(&self, group: F, counter: usize) -> String
+    where
+        F: Fn(&Group) -> Option<Cow<'a, str>>,
+    {
+        let mut output = Output::default();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => output.push(text),
+                Part::Group(g, conversion) => {
+                    let text = group(g).unwrap_or_default();
+                    match conversion {
+                        Some(conversion) => output.push(&convert(&text, *conversion)),
+                        None => output.push(&text),
+                    }
+                }
+                Part::Counter => output.push(&counter.to_string()),
+                Part::CaseStart(case) => output.case = Some(*case),
+                Part::CaseEnd => output.case = None,
+                Part::NextChar(case) => output.next = Some(*case),
+            }
+        }
+        output.text
+    }
+}
+
+/// 大文字・小文字の変換を適用しながらテンプレートの展開結果を溜める
+#[derive(Default)]
+struct Output {
+    text: String,
+    /// `\U`と`\L`による変換
+    case: Option<Case>,
+    /// `\u`と`\l`による次の1文字だけの変換
+    next: Option<Case>,
+}
+
+impl Output {
+    fn push(&mut self, text: &str) {
+        for c in text.chars() {
+            match self.next.take().or(self.case) {
+                Some(Case::Upper) => self.text.extend(c.to_uppercase()),
+                Some(Case::Lower) => self.text.extend(c.to_lowercase()),
+                None => self.text.push(c),
+            }
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn parse_group(name: &str) -> Group {
+    match name.parse() {
+        Ok(index) => Group::Index(index),
+        Err(_) => Group::Name(name.to_string()),
+    }
+}
+
+/// `${...}`の中身を解析する
+fn parse_braced(inner: &str) -> Result<Part, String> {
+    let (name, conversion) = match inner.split_once(':') {
+        Some((name, conversion)) => (name, Some(conversion)),
+        None => (inner, None),
+    };
+    if name == "#" && conversion.is_none() {
+        return Ok(Part::Counter);
+    }
+    if name.is_empty() || !name.chars().all(is_name_char) {
+        return Err(format!("invalid group name '{}'", name));
+    }
+    let conversion = match conversion {
+        None => None,
+        Some("snake") => Some(Conversion::Snake),
+        Some("screaming") => Some(Conversion::Screaming),
+        Some("kebab") => Some(Conversion::Kebab),
+        Some("camel") => Some(Conversion::Camel),
+        Some("pascal") => Some(Conversion::Pascal),
+        Some("upper") => Some(Conversion::Upper),
+        Some("lower") => Some(Conversion::Lower),
+        Some(other) => return Err(format!("unknown conversion '{}'", other)),
+    };
+    Ok(Part::Group(parse_group(name), conversion))
+}
+
+/// 識別子を単語に分ける
+/// `_`や`-`などの記号と、`fooBar`や`HTTPServer`のような大文字の位置で区切る
+fn split_words(identifier: &str) -> Vec<String> {
+    let chars: Vec<char> = identifier.chars().collect();
+    let mut words = Vec::new();
+    let mut current = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if !current.is_empty() && c.is_uppercase() {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_numeric()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                words.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+/// 先頭の1文字だけを大文字にし、残りを小文字にする
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(chars.as_str().to_lowercase().chars())
+            .collect(),
+        None => String::new(),
+    }
+}
+
+fn convert(text: &str, conversion: Conversion) -> String {
+    let words = split_words(text);
+    match conversion {
+        Conversion::Snake => words
+            .iter()
+            .map(|w| w.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("_"),
+        Conversion::Screaming => words
+            .iter()
+            .map(|w| w.to_uppercase())
+            .collect::<Vec<_>>()
+            .join("_"),
+        Conversion::Kebab => words
+            .iter()
+            .map(|w| w.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("-"),
+        Conversion::Camel => words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                if i == 0 {
+                    w.to_lowercase()
+                } else {
+                    capitalize(w)
+                }
+            })
+            .collect(),
+        Conversion::Pascal => words.iter().map(|w| capitalize(w)).collect(),
+        Conversion::Upper => text.to_uppercase(),
+        Conversion::Lower => text.to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+fn render_str(template: &str, pattern: &str, text: &str) -> String {
+    let regex = Regex::new(pattern).unwrap();
+    let caps = regex.captures(text).unwrap();
+    Template::parse(template).unwrap().render(
+        |group| {
+            let m = match group {
+                Group::Index(index) => caps.get(*index),
+                Group::Name(name) => caps.name(name),
+            };
+            m.map(|m| Cow::Borrowed(m.as_str()))
+        },
+        7,
+    )
+}
+
+#[test]
+fn test_render() {
+    assert_eq!(render_str("$2-$1", r"(\w+) (\w+)", "foo bar"), "bar-foo");
+    assert_eq!(
+        render_str("${a}x$b", r"(?P<a>\w+) (?P<b>\w+)", "foo bar"),
+        "fooxbar"
+    );
+    assert_eq!(render_str(r"\U$1\E$1", r"(\w+)", "foo"), "FOOfoo");
+    assert_eq!(render_str(r"\L\u$1", r"(\w+)", "hELLO"), "Hello");
+    assert_eq!(render_str(r"$$1 \$ \\ ${#}", r"x", "x"), r"$1 $ \ 7");
+    assert_eq!(render_str(r"\d", r"x", "x"), r"\d");
+    assert_eq!(render_str("[$9]", r"x", "x"), "[]");
+}
+
+#[test]
+fn test_conversions() {
+    let pattern = r"(\w+)";
+    assert_eq!(
+        render_str("${1:snake}", pattern, "parseHTTPRequest"),
+        "parse_http_request"
+    );
+    assert_eq!(
+        render_str("${1:screaming}", pattern, "maxValue2"),
+        "MAX_VALUE2"
+    );
+    assert_eq!(render_str("${1:kebab}", pattern, "FooBar"), "foo-bar");
+    assert_eq!(render_str("${1:camel}", pattern, "user_id"), "userId");
+    assert_eq!(render_str("${1:pascal}", pattern, "user_id"), "UserId");
+    assert_eq!(render_str("${1:upper}", pattern, "user_id"), "USER_ID");
+}
+
+#[test]
+fn test_parse_errors() {
+    assert!(Template::parse("${1").is_err());
+    assert!(Template::parse("${1:title}").is_err());
+    assert!(Template::parse("${a-b}").is_err());
+    assert_eq!(Template::parse("a$").unwrap(), Template::literal("a$"));
+}
+
+#[test]
+fn test_check() {
+    let regex = Regex::new(r"(?P<name>\w+) (\d+)").unwrap();
+    assert!(Template::parse("$0 $1 $2 ${name}")
+        .unwrap()
+        .check(&regex)
+        .is_ok());
+    assert_eq!(
+        Template::parse("$3 $other $1").unwrap().check(&regex),
+        Err(
+            "the replacement refers to groups that do not exist in the pattern: $3, ${other}"
+                .to_string()
+        )
+    );
+}
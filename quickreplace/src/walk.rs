@@ -0,0 +1,166 @@
+//! 置換対象のファイルをディレクトリから再帰的に集めるモジュール
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+use std::path::PathBuf;
+
+/// バイナリ判定のために先頭から調べるバイト数
+const BINARY_SNIFF_LEN: usize = 8192;
+
+/// ファイル探索の条件
+#[derive(Debug, Default)]
+pub struct WalkOptions {
+    /// 対象に含めるファイルのglobパターン
+    /// 1つでも指定されていれば、いずれかにマッチするファイルだけが対象になる
+    pub include: Vec<String>,
+    /// 対象から除外するファイル・ディレクトリのglobパターン
+    pub exclude: Vec<String>,
+    /// trueなら.gitignoreなどの無視設定を適用しない
+    pub no_ignore: bool,
+}
+
+/// pathsに指定されたファイルとディレクトリ配下を、optionsの条件で辿るWalkBuilderを作る
+/// 隠しファイルと.gitignoreで無視されるファイルは対象外とする
+/// includeはoverridesに加えると無視設定より優先されてしまうので、ここでは加えずinclude_matcherで絞り込む
+fn walk_builder(paths: &[String], options: &WalkOptions) -> Result<WalkBuilder, ignore::Error> {
+    let mut overrides = OverrideBuilder::new(".");
+    for glob in &options.exclude {
+        overrides.add(&format!("!{}", glob))?;
+    }
+
+    let mut builder = WalkBuilder::new(&paths[0]);
+    for path in &paths[1..] {
+        builder.add(path);
+    }
+    builder
+        .overrides(overrides.build()?)
+        .require_git(false)
+        .git_ignore(!options.no_ignore)
+        .git_global(!options.no_ignore)
+        .git_exclude(!options.no_ignore)
+        .ignore(!options.no_ignore)
+        .parents(!options.no_ignore)
+        .sort_by_file_name(|a, b| a.cmp(b));
+    Ok(builder)
+}
+
+/// includeのいずれかにマッチするファイルを見分けるマッチャーを作る
+/// includeが空ならどのファイルにもマッチしない空のマッチャーになる
+fn include_matcher(options: &WalkOptions) -> Result<Override, ignore::Error> {
+    let mut include = OverrideBuilder::new(".");
+    for glob in &options.include {
+        include.add(glob)?;
+    }
+    include.build()
+}
+
+/// pathsに指定されたファイルとディレクトリ配下のファイルをパス順に列挙する
+/// 隠しファイルと.gitignoreで無視されるファイルは、includeにマッチしても対象外とする
+/// 読めないディレクトリなど辿れなかったエントリはon_errorに渡し、残りを辿り続ける
+pub fn collect_files(
+    paths: &[String],
+    options: &WalkOptions,
+    on_error: &mut dyn FnMut(ignore::Error),
+) -> Result<Vec<PathBuf>, ignore::Error> {
+    let include = include_matcher(options)?;
+    let mut files = Vec::new();
+    for entry in walk_builder(paths, options)?.build() {
+        let entry = match entry {
+            Ok(v) => v,
+            Err(e) => {
+                on_error(e);
+                continue;
+            }
+        };
+        if entry.file_type().is_some_and(|t| t.is_file())
+            && (include.is_empty() || include.matched(entry.path(), false).is_whitelist())
+        {
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+/// pathsに指定されたディレクトリ配下のファイルとディレクトリをパス順に列挙する
+/// collect_filesと同じ条件で辿り、pathsに指定されたもの自体は含めない
+/// includeはファイルだけを絞り込み、ディレクトリは全て含める
+pub fn collect_entries(
+    paths: &[String],
+    options: &WalkOptions,
+    on_error: &mut dyn FnMut(ignore::Error),
+) -> Result<Vec<PathBuf>, ignore::Error> {
+    let include = include_matcher(options)?;
+    let mut entries = Vec::new();
+    for entry in walk_builder(paths, options)?.build() {
+        let entry = match entry {
+            Ok(v) => v,
+            Err(e) => {
+                on_error(e);
+                continue;
+            }
+        };
+        let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+        if entry.depth() > 0
+            && (is_dir || include.is_empty() || include.matched(entry.path(), false).is_whitelist())
+        {
+            entries.push(entry.into_path());
+        }
+    }
+    Ok(entries)
+}
+
+/// 先頭部分にNULバイトを含むものをバイナリファイルとみなす
+/// gitやgrepと同じ簡易的な判定方法
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(BINARY_SNIFF_LEN).any(|&b| b == 0)
+}
+
+#[test]
+fn test_is_binary() {
+    assert!(!is_binary(b""));
+    assert!(!is_binary(b"fn main() {}\n"));
+    assert!(is_binary(b"\x7fELF\x02\x01\x01\x00"));
+    let mut late_nul = vec![b'a'; BINARY_SNIFF_LEN];
+    late_nul.push(0);
+    assert!(!is_binary(&late_nul));
+}
+
+#[test]
+fn test_collect_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join(".gitignore"), "secret.rs\n").unwrap();
+    for name in ["lib.rs", "secret.rs", ".hidden.rs", "notes.txt"] {
+        std::fs::write(root.join(name), "foo").unwrap();
+    }
+    let paths = [root.display().to_string()];
+    let names = |options: &WalkOptions| -> Vec<String> {
+        collect_files(&paths, options, &mut |e| panic!("{}", e))
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    };
+    assert_eq!(names(&WalkOptions::default()), vec!["lib.rs", "notes.txt"]);
+    // includeにマッチしても、無視設定と隠しファイルの規則は適用する
+    let include = WalkOptions {
+        include: vec!["*.rs".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(names(&include), vec!["lib.rs"]);
+    let no_ignore = WalkOptions {
+        include: vec!["*.rs".to_string()],
+        no_ignore: true,
+        ..Default::default()
+    };
+    assert_eq!(names(&no_ignore), vec!["lib.rs", "secret.rs"]);
+
+    // 辿れないパスがあっても残りを列挙する
+    let paths = [
+        root.join("missing").display().to_string(),
+        root.display().to_string(),
+    ];
+    let mut errors = 0;
+    let files = collect_files(&paths, &WalkOptions::default(), &mut |_| errors += 1).unwrap();
+    assert_eq!(errors, 1);
+    assert_eq!(files.len(), 2);
+}
@@ -0,0 +1,127 @@
+//! `--watch`で指定されたパスを監視し、変更されたファイルを知らせるモジュール
+//!
+//! 監視にはOSのファイル変更通知(Linuxではinotify)を使う
+//! 生成ツールが多数のファイルを続けて書き出すと大量のイベントが届くので、
+//! イベントが一定時間途切れるまで待ってから1回の変更としてまとめる
+//! quickreplace自身が書き戻したことによるイベントは、内容のハッシュを比べて無視する
+use crate::journal;
+use crossbeam::channel::{self, Receiver, RecvTimeoutError};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// イベントをまとめるために待つ時間の既定値(ミリ秒)
+pub const DEFAULT_DEBOUNCE_MS: u64 = 200;
+
+/// パスの監視
+pub struct Watcher {
+    /// 破棄すると監視が止まるので保持しておく
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// 最後のイベントからこの時間だけ新しいイベントが無ければ変更をまとめて返す
+    debounce: Duration,
+    /// 自分で書き込んだファイルの絶対パスと、書き込んだ内容のSHA-256
+    own_writes: HashMap<PathBuf, String>,
+}
+
+impl Watcher {
+    /// pathsに指定されたファイルとディレクトリ配下の監視を始める
+    pub fn new(paths: &[String], debounce: Duration) -> notify::Result<Watcher> {
+        let (sender, events) = channel::unbounded();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // 受信側が先に破棄されるのは終了する時だけなので、送れなくても構わない
+            let _ = sender.send(event);
+        })?;
+        for path in paths {
+            watcher.watch(Path::new(path), RecursiveMode::Recursive)?;
+        }
+        Ok(Watcher {
+            _watcher: watcher,
+            events,
+            debounce,
+            own_writes: HashMap::new(),
+        })
+    }
+
+    /// 次の変更を待ち、まとめた変更で作成・変更されたファイルの絶対パスを返す
+    /// 自分で書き込んだ内容のままのファイルは含めない
+    pub fn next_changes(&mut self) -> notify::Result<BTreeSet<PathBuf>> {
+        loop {
+            let mut changed = Vec::new();
+            let mut received = self.events.recv().map_err(disconnected)?;
+            loop {
+                let event = received?;
+                if event.kind.is_create() || event.kind.is_modify() {
+                    changed.extend(event.paths);
+                }
+                received = match self.events.recv_timeout(self.debounce) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => return Err(disconnected(())),
+                };
+            }
+            // 相対パスを監視するとイベントのパスも相対パスになるので、絶対パスに揃える
+            let changed: BTreeSet<PathBuf> = changed
+                .iter()
+                .filter(|path| path.is_file())
+                .filter_map(|path| fs::canonicalize(path).ok())
+                .filter(|path| !self.is_own_write(path))
+                .collect();
+            if !changed.is_empty() {
+                return Ok(changed);
+            }
+        }
+    }
+
+    /// pathへ書き込んだことを記録し、そのイベントで再び置換しないようにする
+    pub fn record_write(&mut self, path: &Path) {
+        if let (Ok(path), Ok(data)) = (fs::canonicalize(path), fs::read(path)) {
+            self.own_writes.insert(path, journal::hash(&data));
+        }
+    }
+
+    /// pathの内容が最後に自分で書き込んだ内容のままかどうかを返す
+    /// 他から変更されていれば記録を消す
+    fn is_own_write(&mut self, path: &Path) -> bool {
+        let Some(written) = self.own_writes.get(path) else {
+            return false;
+        };
+        if fs::read(path).is_ok_and(|data| journal::hash(&data) == *written) {
+            return true;
+        }
+        self.own_writes.remove(path);
+        false
+    }
+}
+
+/// 監視が止まったことを表すエラー
+fn disconnected<T>(_: T) -> notify::Error {
+    notify::Error::generic("the file watcher stopped")
+}
+
+#[test]
+fn test_watcher() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = fs::canonicalize(dir.path()).unwrap();
+    let path = root.join("generated.rs");
+    let mut watcher = Watcher::new(
+        &[root.display().to_string()],
+        Duration::from_millis(DEFAULT_DEBOUNCE_MS),
+    )
+    .unwrap();
+
+    fs::write(&path, "old").unwrap();
+    fs::write(&path, "old old").unwrap();
+    assert_eq!(
+        watcher.next_changes().unwrap(),
+        BTreeSet::from([path.clone()])
+    );
+
+    watcher.record_write(&path);
+    assert!(watcher.is_own_write(&path));
+    fs::write(&path, "regenerated").unwrap();
+    assert!(!watcher.is_own_write(&path));
+    assert!(watcher.own_writes.is_empty());
+}
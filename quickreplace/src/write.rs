@@ -0,0 +1,130 @@
+//! 書き込み途中でクラッシュしても元のファイルが壊れないように置換結果を保存するモジュール
+use std::fs::{self, File, FileTimes, Metadata};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// バックアップファイルの拡張子の既定値
+pub const DEFAULT_BACKUP_SUFFIX: &str = ".bak";
+
+/// ファイルの書き込み方法
+#[derive(Debug, Default)]
+pub struct WriteOptions {
+    /// 指定されていれば、元のファイルをこの拡張子を付けた名前でバックアップする
+    pub backup_suffix: Option<String>,
+    /// trueなら書き込み後も元のファイルの更新日時を保つ
+    pub preserve_mtime: bool,
+}
+
+/// 書き込み先と同じディレクトリの一時ファイルに書き込み、
+/// commitした時点で書き込み先と置き換えるライター
+/// 途中で失敗しても元のファイルは完全に残るか、完全に置き換わるかのどちらかになる
+/// commitせずにdropした場合は一時ファイルを削除し、書き込み先には何もしない
+pub struct AtomicFile<'a> {
+    /// 書き込み先のパス
+    path: PathBuf,
+    /// 書き込み先のディレクトリ
+    dir: PathBuf,
+    /// 書き込み先に元々あったファイルのメタデータ
+    original: Option<Metadata>,
+    /// 書き込み中の一時ファイル
+    temp: BufWriter<NamedTempFile>,
+    options: &'a WriteOptions,
+}
+
+impl<'a> AtomicFile<'a> {
+    /// pathへ書き込むための一時ファイルを作る
+    pub fn create(path: &Path, options: &'a WriteOptions) -> io::Result<AtomicFile<'a>> {
+        // シンボリックリンクはリンク自体ではなくリンク先を書き換える
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let dir = match path.parent() {
+            Some(v) if !v.as_os_str().is_empty() => v.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let original = fs::metadata(&path).ok();
+        let temp = BufWriter::new(NamedTempFile::new_in(&dir)?);
+        Ok(AtomicFile {
+            path,
+            dir,
+            original,
+            temp,
+            options,
+        })
+    }
+
+    /// 書き込んだ内容をfsyncしてから書き込み先と置き換える
+    /// 既存のファイルを置き換える場合はパーミッションを引き継ぐ
+    pub fn commit(self) -> io::Result<()> {
+        let temp = self.temp.into_inner().map_err(|e| e.into_error())?;
+        if let Some(metadata) = &self.original {
+            temp.as_file().set_permissions(metadata.permissions())?;
+            if self.options.preserve_mtime {
+                let times = FileTimes::new()
+                    .set_accessed(metadata.accessed()?)
+                    .set_modified(metadata.modified()?);
+                temp.as_file().set_times(times)?;
+            }
+        }
+        temp.as_file().sync_all()?;
+
+        if let (Some(suffix), Some(_)) = (&self.options.backup_suffix, &self.original) {
+            let mut backup = self.path.clone().into_os_string();
+            backup.push(suffix);
+            fs::copy(&self.path, backup)?;
+        }
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        // 名前の変更自体を永続化するためにディレクトリもfsyncする
+        if cfg!(unix) {
+            File::open(&self.dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.temp.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.temp.flush()
+    }
+}
+
+/// dataをpathへアトミックに書き込む
+pub fn write_atomic(path: &Path, data: &[u8], options: &WriteOptions) -> io::Result<()> {
+    let mut file = AtomicFile::create(path, options)?;
+    file.write_all(data)?;
+    file.commit()
+}
+
+#[test]
+fn test_write_atomic() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.txt");
+    fs::write(&path, "before").unwrap();
+    let options = WriteOptions {
+        backup_suffix: Some(DEFAULT_BACKUP_SUFFIX.to_string()),
+        preserve_mtime: true,
+    };
+    let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+    write_atomic(&path, b"after", &options).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "after");
+    assert_eq!(
+        fs::read_to_string(dir.path().join("file.txt.bak")).unwrap(),
+        "before"
+    );
+    assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), mtime);
+    // 一時ファイルは残らない
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+    let new_path = dir.path().join("new.txt");
+    write_atomic(&new_path, b"new", &WriteOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(&new_path).unwrap(), "new");
+
+    // commitしなければ書き込み先は変わらない
+    let mut file = AtomicFile::create(&new_path, &options).unwrap();
+    file.write_all(b"discarded").unwrap();
+    drop(file);
+    assert_eq!(fs::read_to_string(&new_path).unwrap(), "new");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+}